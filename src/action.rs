@@ -1,109 +1,464 @@
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
 use std::fs;
+use std::io;
 
-use context::MapFileContext;
+use context::{CopyMode, LinkFallback, MapFileContext};
+use conflict::resolve_conflict;
+use copy::{copy_file, copy_for_move, verify_copy};
+use preserve::preserve_attributes;
 use error::*;
+use journal::{Journal, Operation};
+use summary::Outcome;
+use template::{Destination, TemplateVariables};
 
 pub trait MapAction {
-    fn create_task<'a>(&self, file: PathBuf) -> MapFileTask<'a>;
+    fn create_task<'a>(&self, file: PathBuf, variables: &TemplateVariables, file_context: &MapFileContext)
+        -> Result<MapFileTask<'a>>;
+}
+
+/// The kinds of action a rule can take, one for each directive.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    Copy,
+    Move,
+    Symlink,
+    RelativeSymlink,
+    Hardlink
+}
+
+/// What a task will do, as data that can be saved and later turned back into the same task with `action_task`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaskDescription {
+    pub action: ActionKind,
+    pub source: PathBuf,
+    /// The path the source is mapped to, relative to the destination directory.
+    pub destination: PathBuf,
+    /// The rule that matched the source, as it's shown in logs.
+    pub rule: String
 }
 
 pub struct MapFileTask<'a> {
-    task: Box<FnMut(&MapFileContext) -> Result<()> + 'a>
+    task: Box<FnMut(&MapFileContext, &mut Journal) -> Result<Outcome> + 'a>,
+    description: Option<TaskDescription>
 }
 
 impl<'a> MapFileTask<'a> {
-    pub fn execute(mut self, file_context: &MapFileContext) -> Result<()> {
-        let task_function: &mut FnMut(&MapFileContext) -> Result<()> = &mut *self.task;
-        task_function(file_context)
+    /// Runs the task, recording its changes in `journal`. If it fails, the changes it made are undone, so it
+    /// either completes or leaves nothing behind.
+    pub fn execute(mut self, file_context: &MapFileContext, journal: &mut Journal) -> Result<Outcome> {
+        let task_function: &mut FnMut(&MapFileContext, &mut Journal) -> Result<Outcome> = &mut *self.task;
+        let savepoint = journal.savepoint();
+        let result = task_function(file_context, journal);
+        if result.is_err() {
+            if let Err(error) = journal.roll_back_to(savepoint) {
+                error!("{}", error);
+            }
+        }
+        result
+    }
+
+    pub fn new<T>(task_function: T) -> MapFileTask<'a>
+        where T: FnMut(&MapFileContext, &mut Journal) -> Result<Outcome> + 'a {
+        MapFileTask { task: Box::new(task_function), description: None }
+    }
+
+    /// Records what this task will do, if it can be known up front.
+    pub fn with_description(mut self, description: TaskDescription) -> MapFileTask<'a> {
+        self.description = Some(description);
+        self
+    }
+
+    /// Records the rule that matched the file, if what this task will do is known.
+    pub fn with_rule(mut self, rule: String) -> MapFileTask<'a> {
+        if let Some(ref mut description) = self.description {
+            description.rule = rule;
+        }
+        self
+    }
+
+    pub fn description(&self) -> Option<&TaskDescription> {
+        self.description.as_ref()
     }
 
-    pub fn new<T>(task_function: T) -> MapFileTask<'a> where T: FnMut(&MapFileContext) -> Result<()> + 'a {
-        MapFileTask { task: Box::new(task_function) }
+    /// The path, relative to the destination directory, that this task will write to.
+    pub fn destination(&self) -> Option<&PathBuf> {
+        self.description.as_ref().map(|description| &description.destination)
+    }
+}
+
+/// The task that performs `action` on `file`, mapping it into `relative_destination` with the name `file_name`, or
+/// its own name if there isn't one.
+pub fn action_task<'a>(action: ActionKind, file: PathBuf, relative_destination: PathBuf, file_name: Option<OsString>)
+    -> MapFileTask<'a> {
+    let planned_destination = planned_destination(&file, &relative_destination, &file_name);
+    let task = match action {
+        ActionKind::Copy => MapFileTask::new(copy_task(file.clone(), relative_destination, file_name)),
+        ActionKind::Move => MapFileTask::new(move_task(file.clone(), relative_destination, file_name)),
+        ActionKind::Symlink =>
+            MapFileTask::new(symlink_task(file.clone(), relative_destination, file_name, SymlinkKind::Absolute)),
+        ActionKind::RelativeSymlink =>
+            MapFileTask::new(symlink_task(file.clone(), relative_destination, file_name, SymlinkKind::Relative)),
+        ActionKind::Hardlink => MapFileTask::new(hardlink_task(file.clone(), relative_destination, file_name)),
+    };
+    match planned_destination {
+        Some(destination) => task.with_description(TaskDescription { action, source: file, destination, rule: String::new() }),
+        None => task
     }
 }
 
 pub struct CopyAction {
-    relative_destination: PathBuf
+    relative_destination: Destination
 }
 
 impl CopyAction {
-    pub fn new(relative_destination: PathBuf) -> CopyAction {
+    pub fn new(relative_destination: Destination) -> CopyAction {
         CopyAction { relative_destination }
     }
 }
 
 impl MapAction for CopyAction {
-    fn create_task<'a>(&self, file: PathBuf) -> MapFileTask<'a> {
-        let relative_destination = self.relative_destination.clone();
-        let task = move |file_context: &MapFileContext| {
-            perform_file_operation(&file, file_context, &relative_destination, |destination: &PathBuf| {
-                info!("Copying {} -> {}", file.to_string_lossy(), destination.to_string_lossy());
-                if !file_context.dry_run {
-                    fs::copy(&file, &destination)
-                        .chain_err(|| format!("Unable to copy file {} to destination {}", file.to_string_lossy(),
-                                            &destination.to_string_lossy()))?;
-                }
-                Ok(())
-            })
-        };
+    fn create_task<'a>(&self, file: PathBuf, variables: &TemplateVariables, file_context: &MapFileContext)
+        -> Result<MapFileTask<'a>> {
+        let relative_destination = self.relative_destination.render_directory(&file, variables, file_context)?;
+        let file_name = self.relative_destination.render_file_name(&file, variables, file_context)?;
+        Ok(action_task(ActionKind::Copy, file, relative_destination, file_name))
+    }
+}
 
-        MapFileTask::new(task)
+fn copy_task(file: PathBuf, relative_destination: PathBuf, file_name: Option<OsString>)
+    -> impl FnMut(&MapFileContext, &mut Journal) -> Result<Outcome> {
+    move |file_context: &MapFileContext, journal: &mut Journal| {
+        perform_file_operation(&file, file_context, journal, &relative_destination, &file_name,
+                               |destination: &PathBuf, journal: &mut Journal| {
+            info!("Copying {} -> {}", file.to_string_lossy(), destination.to_string_lossy());
+            copy(&file, destination, file_context, journal)
+        })
     }
 }
 
 pub struct MoveAction {
-    relative_destination: PathBuf
+    relative_destination: Destination
 }
 
 impl MoveAction {
-    pub fn new(relative_destination: PathBuf) -> MoveAction {
+    pub fn new(relative_destination: Destination) -> MoveAction {
         MoveAction { relative_destination }
     }
 }
 
 impl MapAction for MoveAction {
-    fn create_task<'a>(&self, file: PathBuf) -> MapFileTask<'a> {
-        let relative_destination = self.relative_destination.clone();
-        let task = move |file_context: &MapFileContext| {
-            perform_file_operation(&file, file_context, &relative_destination, |destination: &PathBuf| {
-                info!("Moving {} -> {}", file.to_string_lossy(), destination.to_string_lossy());
+    fn create_task<'a>(&self, file: PathBuf, variables: &TemplateVariables, file_context: &MapFileContext)
+        -> Result<MapFileTask<'a>> {
+        let relative_destination = self.relative_destination.render_directory(&file, variables, file_context)?;
+        let file_name = self.relative_destination.render_file_name(&file, variables, file_context)?;
+        Ok(action_task(ActionKind::Move, file, relative_destination, file_name))
+    }
+}
+
+fn move_task(file: PathBuf, relative_destination: PathBuf, file_name: Option<OsString>)
+    -> impl FnMut(&MapFileContext, &mut Journal) -> Result<Outcome> {
+    move |file_context: &MapFileContext, journal: &mut Journal| {
+        let operation = |destination: &PathBuf, journal: &mut Journal| {
+            info!("Moving {} -> {}", file.to_string_lossy(), destination.to_string_lossy());
+            let cross_device = if file_context.dry_run {
+                on_different_devices(&file, destination)
+            } else {
+                match fs::rename(&file, destination) {
+                    Ok(()) => {
+                        journal.record(Operation::Renamed { from: file.clone(), to: destination.clone() });
+                        false
+                    }
+                    Err(ref error) if error.kind() == io::ErrorKind::CrossesDevices => true,
+                    Err(error) => return Err(error).chain_err(|| format!("Unable to move file {} to destination {}",
+                                                                        file.to_string_lossy(), destination.to_string_lossy()))
+                }
+            };
+            if cross_device {
+                info!("Moving {} by copying it and then deleting it, as the destination is on a different device",
+                      file.to_string_lossy());
                 if !file_context.dry_run {
-                    fs::rename(&file, &destination)
-                        .chain_err(|| format!("Unable to move file {} to destination {}", file.to_string_lossy(),
-                                            &destination.to_string_lossy()))?;
+                    let strategy = copy_for_move(&file, destination, file_context)?;
+                    debug!("Copied {} using {}", file.to_string_lossy(), strategy);
+                    journal.record(Operation::Copied { source: file.clone(), destination: destination.clone() });
+                    journal.record(Operation::DeleteOnCommit { file: file.clone(), duplicate: destination.clone() });
                 }
-                Ok(())
-            })
+            }
+            Ok(())
         };
+        let outcome = perform_file_operation(&file, file_context, journal, &relative_destination, &file_name,
+                                             operation)?;
+        if let Outcome::AlreadyMapped(ref duplicate) = outcome {
+            info!("Deleting {}, as it's already mapped", file.to_string_lossy());
+            if !file_context.dry_run {
+                journal.record(Operation::DeleteOnCommit { file: file.clone(), duplicate: duplicate.clone() });
+            }
+        }
+        Ok(outcome)
+    }
+}
+
+/// Whether a symbolic link refers to its file by absolute path or by a path relative to the link.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SymlinkKind {
+    Absolute,
+    Relative
+}
+
+pub struct SymlinkAction {
+    relative_destination: Destination,
+    kind: SymlinkKind
+}
 
-        MapFileTask::new(task)
+impl SymlinkAction {
+    pub fn new(relative_destination: Destination, kind: SymlinkKind) -> SymlinkAction {
+        SymlinkAction { relative_destination, kind }
     }
 }
 
-fn perform_file_operation(file: &PathBuf, file_context: &MapFileContext, relative_destination: &PathBuf, 
-                          mut operation: impl FnMut(&PathBuf) -> Result<()>) -> Result<()> {
-    let output_directory = create_output_directory(&file_context.dest_dir, relative_destination, file_context.dry_run)?;
-    let file_name = match file.file_name() {
-        Some(name) => name,
-        None => bail!("Internal failure: File {} does not have a file name. This is a bug.", file.to_string_lossy())
+impl MapAction for SymlinkAction {
+    fn create_task<'a>(&self, file: PathBuf, variables: &TemplateVariables, file_context: &MapFileContext)
+        -> Result<MapFileTask<'a>> {
+        let relative_destination = self.relative_destination.render_directory(&file, variables, file_context)?;
+        let file_name = self.relative_destination.render_file_name(&file, variables, file_context)?;
+        let action = match self.kind {
+            SymlinkKind::Absolute => ActionKind::Symlink,
+            SymlinkKind::Relative => ActionKind::RelativeSymlink
+        };
+        Ok(action_task(action, file, relative_destination, file_name))
+    }
+}
+
+fn symlink_task(file: PathBuf, relative_destination: PathBuf, file_name: Option<OsString>, kind: SymlinkKind)
+    -> impl FnMut(&MapFileContext, &mut Journal) -> Result<Outcome> {
+    move |file_context: &MapFileContext, journal: &mut Journal| {
+        perform_file_operation(&file, file_context, journal, &relative_destination, &file_name,
+                               |destination: &PathBuf, journal: &mut Journal| {
+            let absolute_file = absolute_path(&file)?;
+            let target = match kind {
+                SymlinkKind::Absolute => absolute_file,
+                SymlinkKind::Relative => {
+                    let link_directory = absolute_path(destination.parent().unwrap_or_else(|| Path::new("")))?;
+                    path_between(&link_directory, &absolute_file)
+                }
+            };
+            info!("Symlinking {} -> {}", target.to_string_lossy(), destination.to_string_lossy());
+            if !file_context.dry_run {
+                create_symlink(&target, destination)
+                    .chain_err(|| format!("Unable to create symlink {} to file {}", destination.to_string_lossy(),
+                                        file.to_string_lossy()))?;
+                journal.record(Operation::Linked { source: file.clone(), destination: destination.clone() });
+            }
+            Ok(())
+        })
+    }
+}
+
+pub struct HardlinkAction {
+    relative_destination: Destination
+}
+
+impl HardlinkAction {
+    pub fn new(relative_destination: Destination) -> HardlinkAction {
+        HardlinkAction { relative_destination }
+    }
+}
+
+impl MapAction for HardlinkAction {
+    fn create_task<'a>(&self, file: PathBuf, variables: &TemplateVariables, file_context: &MapFileContext)
+        -> Result<MapFileTask<'a>> {
+        let relative_destination = self.relative_destination.render_directory(&file, variables, file_context)?;
+        let file_name = self.relative_destination.render_file_name(&file, variables, file_context)?;
+        Ok(action_task(ActionKind::Hardlink, file, relative_destination, file_name))
+    }
+}
+
+fn hardlink_task(file: PathBuf, relative_destination: PathBuf, file_name: Option<OsString>)
+    -> impl FnMut(&MapFileContext, &mut Journal) -> Result<Outcome> {
+    move |file_context: &MapFileContext, journal: &mut Journal| {
+        perform_file_operation(&file, file_context, journal, &relative_destination, &file_name,
+                               |destination: &PathBuf, journal: &mut Journal| {
+            info!("Hard linking {} -> {}", file.to_string_lossy(), destination.to_string_lossy());
+            let cross_device = if file_context.dry_run {
+                on_different_devices(&file, destination)
+            } else {
+                match fs::hard_link(&file, destination) {
+                    Ok(()) => {
+                        journal.record(Operation::Linked { source: file.clone(), destination: destination.clone() });
+                        false
+                    }
+                    Err(ref error) if error.kind() == io::ErrorKind::CrossesDevices => true,
+                    Err(error) => return Err(error).chain_err(|| format!("Unable to hard link file {} to destination {}",
+                                                                        file.to_string_lossy(), destination.to_string_lossy()))
+                }
+            };
+            if cross_device {
+                copy_across_devices(&file, destination, file_context, journal)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Copies `file` to `destination` when it can't be hard linked there, if `--link-fallback copy` allows it.
+fn copy_across_devices(file: &Path, destination: &Path, file_context: &MapFileContext, journal: &mut Journal)
+    -> Result<()> {
+    if file_context.link_fallback == LinkFallback::Fail {
+        bail!("Unable to hard link file {} to destination {}, as they are on different devices. \
+               Use --link-fallback copy to copy it instead", file.to_string_lossy(), destination.to_string_lossy())
+    }
+    info!("Copying {} -> {}, as they are on different devices", file.to_string_lossy(), destination.to_string_lossy());
+    copy(file, destination, file_context, journal)
+}
+
+/// Copies `file` to `destination` as `--copy-mode` and `--preserve` say, and checks the copy if `--verify` is
+/// set, unless this is a dry run.
+fn copy(file: &Path, destination: &Path, file_context: &MapFileContext, journal: &mut Journal) -> Result<()> {
+    if file_context.dry_run {
+        return Ok(());
+    }
+    // Before copying, so that a copy that fails part way through is removed too
+    journal.record(Operation::Copied { source: file.to_path_buf(), destination: destination.to_path_buf() });
+    let strategy = copy_file(file, destination, file_context.copy_mode).chain_err(|| {
+        let hint = match file_context.copy_mode {
+            CopyMode::Reflink => ". Use --copy-mode auto to fall back to a full copy",
+            _ => ""
+        };
+        format!("Unable to copy file {} to destination {}{}", file.to_string_lossy(),
+                destination.to_string_lossy(), hint)
+    })?;
+    debug!("Copied {} using {}", file.to_string_lossy(), strategy);
+    preserve_attributes(file, destination, file_context.preserve)?;
+    if file_context.verify {
+        verify_copy(file, destination, file_context.hash_algorithm)?;
+        debug!("Verified {}", destination.to_string_lossy());
+    }
+    Ok(())
+}
+
+/// Whether `file` is on a different device to the directory `destination` will be created in, as far as can be
+/// told before it's created.
+#[cfg(unix)]
+fn on_different_devices(file: &Path, destination: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    let directory = destination.ancestors().skip(1).find(|ancestor| ancestor.is_dir()).unwrap_or_else(|| Path::new("."));
+    match (fs::metadata(file), fs::metadata(directory)) {
+        (Ok(file_metadata), Ok(directory_metadata)) => file_metadata.dev() != directory_metadata.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn on_different_devices(_file: &Path, _destination: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    ::std::os::windows::fs::symlink_file(target, link)
+}
+
+/// The absolute form of `path`, which needn't exist yet, with symlinks in the part that does exist resolved.
+fn absolute_path(path: &Path) -> Result<PathBuf> {
+    let mut missing_names = Vec::new();
+    let mut ancestor = path;
+    loop {
+        let existing = if ancestor.as_os_str().is_empty() { Path::new(".") } else { ancestor };
+        if let Ok(canonical) = existing.canonicalize() {
+            return Ok(missing_names.into_iter().rev().fold(canonical, |path: PathBuf, name| path.join(name)));
+        }
+        match (ancestor.file_name(), ancestor.parent()) {
+            (Some(name), Some(parent)) => {
+                missing_names.push(name);
+                ancestor = parent;
+            }
+            _ => bail!("Unable to find the absolute path of {}", path.to_string_lossy())
+        }
+    }
+}
+
+/// The relative path from `directory` to `target`, both of which must be absolute.
+fn path_between(directory: &Path, target: &Path) -> PathBuf {
+    let directory_components: Vec<_> = directory.components().collect();
+    let target_components: Vec<_> = target.components().collect();
+    let common = directory_components.iter().zip(&target_components).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..directory_components.len() {
+        path.push("..");
+    }
+    for component in &target_components[common..] {
+        path.push(component.as_os_str());
+    }
+    path
+}
+
+fn perform_file_operation(file: &PathBuf, file_context: &MapFileContext, journal: &mut Journal,
+                          relative_destination: &PathBuf, file_name: &Option<OsString>,
+                          mut operation: impl FnMut(&PathBuf, &mut Journal) -> Result<()>) -> Result<Outcome> {
+    let output_directory = create_output_directory(&file_context.dest_dir, relative_destination, file_context.dry_run,
+                                                   journal)?;
+    let file_name = match file_name {
+        Some(name) => name.as_os_str(),
+        None => match file.file_name() {
+            Some(name) => name,
+            None => bail!("Internal failure: File {} does not have a file name. This is a bug.", file.to_string_lossy())
+        }
     };
     let destination: PathBuf = output_directory.join(file_name);
-    operation(&destination)
+    let outcome = resolve_conflict(file_context, file, &destination)?;
+    match outcome {
+        Outcome::Skipped | Outcome::AlreadyMapped(_) => (),
+        Outcome::Renamed(ref renamed) => operation(renamed, journal)?,
+        Outcome::Overwritten => {
+            // Kept until the run is committed, in case it has to be rolled back
+            if !file_context.dry_run {
+                journal.replace_file(&destination)?;
+            }
+            operation(&destination, journal)?
+        }
+        Outcome::Mapped => operation(&destination, journal)?,
+    }
+    Ok(outcome)
+}
+
+/// The path, relative to the destination directory, that `file` will be mapped to if it can be known up front.
+fn planned_destination(file: &Path, relative_destination: &Path, file_name: &Option<OsString>) -> Option<PathBuf> {
+    match file_name {
+        Some(name) => Some(relative_destination.join(name)),
+        None => file.file_name().map(|name| relative_destination.join(name))
+    }
 }
 
 fn create_output_directory(
     destination_directory: &PathBuf,
     relative_output_directory: &PathBuf,
     dry_run: bool,
+    journal: &mut Journal,
 ) -> Result<PathBuf> {
     let destination_directory: PathBuf = destination_directory.join(&relative_output_directory);
     if !destination_directory.is_dir() {
         info!("Creating destination directory: {}", destination_directory.to_string_lossy());
         if !dry_run {
-            fs::create_dir_all(&destination_directory)
-                .chain_err(|| format!("Unable to create destination directory: {}",
-                                      destination_directory.to_string_lossy()))?
+            // One at a time, outermost first, so that each one created can be removed again
+            let missing: Vec<&Path> = destination_directory.ancestors()
+                .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.is_dir())
+                .collect();
+            for directory in missing.into_iter().rev() {
+                match fs::create_dir(directory) {
+                    Ok(()) => journal.record(Operation::CreatedDirectory(directory.to_path_buf())),
+                    // Paths through `..` exist once the directory before them is created
+                    Err(_) if directory.is_dir() => (),
+                    Err(error) => return Err(error).chain_err(|| format!("Unable to create destination directory: {}",
+                                                                        directory.to_string_lossy())),
+                }
+            }
         }
     }
 
@@ -113,21 +468,23 @@ fn create_output_directory(
 #[cfg(test)]
 mod test {
     use super::*;
+    use context::ConflictPolicy;
     use testutils::*;
 
     #[test]
     fn copy_action_task_dry_run_does_not_create_output_directory() {
-        let action = CopyAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = CopyAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: true
+                        dry_run: true,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    task.execute(&map_file_context).unwrap();
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
                     assert_eq!(output_directory.is_dir(), false);
                 })
             })
@@ -136,17 +493,18 @@ mod test {
 
     #[test]
     fn copy_action_task_creates_output_directory() {
-        let action = CopyAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = CopyAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    task.execute(&map_file_context).unwrap();
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
                     assert_eq!(output_directory.is_dir(), true);
                 })
             })
@@ -155,17 +513,18 @@ mod test {
 
     #[test]
     fn copy_action_task_create_output_directory_failure() {
-        let action = CopyAction::new(PathBuf::from("../../../../../../../../".to_string() + &random_string() + "_i,l|l;e:g'al\"name"));
+        let action = CopyAction::new(Destination::parse(&("../../../../../../../../".to_string() + &random_string() + "_i,l|l;e:g'al\"name")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    let result = task.execute(&map_file_context);
+                    let result = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context)
+                        .and_then(|task| task.execute(&map_file_context, &mut Journal::default()));
                     assert_eq!(result.is_err(), true);
                 })
             })
@@ -174,17 +533,18 @@ mod test {
 
     #[test]
     fn copy_action_task_file_has_no_file_name() {
-        let action = CopyAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = CopyAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.join(PathBuf::from("..")));
-                    let result = task.execute(&map_file_context);
+                    let task = action.create_task(test_file.join(PathBuf::from("..")), &TemplateVariables::new(), &map_file_context).unwrap();
+                    let result = task.execute(&map_file_context, &mut Journal::default());
                     assert_eq!(result.is_err(), true);
                 })
             })
@@ -193,18 +553,19 @@ mod test {
 
     #[test]
     fn copy_action_task_dry_run_does_not_copy_file() {
-        let action = CopyAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = CopyAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: true
+                        dry_run: true,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    task.execute(&map_file_context).unwrap();
-                    assert_eq!(action.relative_destination.join(test_file.file_name().unwrap()).is_file(),
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                    assert_eq!(PathBuf::from(action.relative_destination.to_string()).join(test_file.file_name().unwrap()).is_file(),
                                false);
                 })
             })
@@ -213,17 +574,18 @@ mod test {
 
     #[test]
     fn copy_action_task_file_copy_failure() {
-        let action = CopyAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = CopyAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.join("_i,l|l;e:g'al\"name"));
-                    let result = task.execute(&map_file_context);
+                    let task = action.create_task(test_file.join("_i,l|l;e:g'al\"name"), &TemplateVariables::new(), &map_file_context).unwrap();
+                    let result = task.execute(&map_file_context, &mut Journal::default());
                     assert_eq!(result.is_err(), true);
                 })
             })
@@ -232,18 +594,19 @@ mod test {
 
     #[test]
     fn copy_action_task_file_copies_file() {
-        let action = CopyAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = CopyAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    task.execute(&map_file_context).unwrap();
-                    assert_eq!(output_directory.join(action.relative_destination.join(test_file.file_name().unwrap())).is_file(), true);
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                    assert_eq!(output_directory.join(PathBuf::from(action.relative_destination.to_string()).join(test_file.file_name().unwrap())).is_file(), true);
                     assert_eq!(test_file.is_file(), true);
                 })
             })
@@ -252,17 +615,18 @@ mod test {
 
     #[test]
     fn move_action_task_dry_run_does_not_create_output_directory() {
-        let action = MoveAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = MoveAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: true
+                        dry_run: true,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    task.execute(&map_file_context).unwrap();
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
                     assert_eq!(output_directory.is_dir(), false);
                 })
             })
@@ -271,17 +635,18 @@ mod test {
 
     #[test]
     fn move_action_task_creates_output_directory() {
-        let action = MoveAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = MoveAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    task.execute(&map_file_context).unwrap();
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
                     assert_eq!(output_directory.is_dir(), true);
                 })
             })
@@ -290,17 +655,18 @@ mod test {
 
     #[test]
     fn move_action_task_create_output_directory_failure() {
-        let action = MoveAction::new(PathBuf::from("../../../../../../../../".to_string() + &random_string() + "_i,l|l;e:g'al\"name"));
+        let action = MoveAction::new(Destination::parse(&("../../../../../../../../".to_string() + &random_string() + "_i,l|l;e:g'al\"name")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    let result = task.execute(&map_file_context);
+                    let result = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context)
+                        .and_then(|task| task.execute(&map_file_context, &mut Journal::default()));
                     assert_eq!(result.is_err(), true);
                 })
             })
@@ -309,17 +675,18 @@ mod test {
 
     #[test]
     fn move_action_task_file_has_no_file_name() {
-        let action = MoveAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = MoveAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.join(PathBuf::from("..")));
-                    let result = task.execute(&map_file_context);
+                    let task = action.create_task(test_file.join(PathBuf::from("..")), &TemplateVariables::new(), &map_file_context).unwrap();
+                    let result = task.execute(&map_file_context, &mut Journal::default());
                     assert_eq!(result.is_err(), true);
                 })
             })
@@ -328,18 +695,19 @@ mod test {
 
     #[test]
     fn move_action_task_dry_run_does_not_move_file() {
-        let action = MoveAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = MoveAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: true
+                        dry_run: true,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    task.execute(&map_file_context).unwrap();
-                    assert_eq!(action.relative_destination.join(test_file.file_name().unwrap()).is_file(),
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                    assert_eq!(PathBuf::from(action.relative_destination.to_string()).join(test_file.file_name().unwrap()).is_file(),
                                false);
                     assert_eq!(test_file.is_file(), true);
                 })
@@ -349,17 +717,18 @@ mod test {
 
     #[test]
     fn move_action_task_file_move_failure() {
-        let action = MoveAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = MoveAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.join("_i,l|l;e:g'al\"name"));
-                    let result = task.execute(&map_file_context);
+                    let task = action.create_task(test_file.join("_i,l|l;e:g'al\"name"), &TemplateVariables::new(), &map_file_context).unwrap();
+                    let result = task.execute(&map_file_context, &mut Journal::default());
                     assert_eq!(result.is_err(), true);
                 })
             })
@@ -368,21 +737,287 @@ mod test {
 
     #[test]
     fn move_action_task_file_moves_file() {
-        let action = MoveAction::new(PathBuf::from(random_string() + "_destination"));
+        let action = MoveAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
                 with_default_output_directory(test_directory, |output_directory| {
                     let map_file_context = MapFileContext {
                         source_dir: test_directory.clone(),
                         dest_dir: output_directory.clone(),
-                        dry_run: false
+                        dry_run: false,
+                        ..Default::default()
                     };
-                    let task = action.create_task(test_file.clone());
-                    task.execute(&map_file_context).unwrap();
-                    assert_eq!(output_directory.join(action.relative_destination.join(test_file.file_name().unwrap())).is_file(), true);
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                    assert_eq!(output_directory.join(PathBuf::from(action.relative_destination.to_string()).join(test_file.file_name().unwrap())).is_file(), true);
                     assert_eq!(test_file.is_file(), false);
                 })
             })
         });
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn copy_action_task_renders_destination_template() {
+        let action = CopyAction::new(Destination::parse("${ext}/${1}").unwrap());
+        let mut variables = TemplateVariables::new();
+        variables.insert("1".to_string(), random_string() + "_captured");
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                with_default_output_directory(test_directory, |output_directory| {
+                    let map_file_context = MapFileContext {
+                        source_dir: test_directory.clone(),
+                        dest_dir: output_directory.clone(),
+                        dry_run: false,
+                        ..Default::default()
+                    };
+                    let task = action.create_task(test_file.clone(), &variables, &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                    let expected_directory = output_directory.join("test").join(variables.get("1").unwrap());
+                    assert!(expected_directory.join(test_file.file_name().unwrap()).is_file());
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn copy_action_create_task_unknown_variable() {
+        let action = CopyAction::new(Destination::parse("${unknown}").unwrap());
+        let result = action.create_task(PathBuf::from("file.txt"), &TemplateVariables::new(), &dummy_map_file_context());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn copy_action_task_renames_file() {
+        let action = CopyAction::new(Destination::parse("renamed -> ${stem:upper}.copy").unwrap());
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                with_default_output_directory(test_directory, |output_directory| {
+                    let map_file_context = MapFileContext {
+                        source_dir: test_directory.clone(),
+                        dest_dir: output_directory.clone(),
+                        dry_run: false,
+                        ..Default::default()
+                    };
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    let expected_name = test_file.file_stem().unwrap().to_string_lossy().to_uppercase() + ".copy";
+                    assert_eq!(task.destination(), Some(&PathBuf::from("renamed").join(&expected_name)));
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                    assert!(output_directory.join("renamed").join(&expected_name).is_file());
+                    assert!(test_file.is_file());
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn symlink_action_task_links_file() {
+        for &kind in &[SymlinkKind::Absolute, SymlinkKind::Relative] {
+            let action = SymlinkAction::new(Destination::parse(&(random_string() + "_destination")).unwrap(), kind);
+            with_default_test_directory(|test_directory| {
+                with_default_test_file(test_directory, |test_file| {
+                    with_default_output_directory(test_directory, |output_directory| {
+                        let map_file_context = MapFileContext {
+                            source_dir: test_directory.clone(),
+                            dest_dir: output_directory.clone(),
+                            dry_run: false,
+                            ..Default::default()
+                        };
+                        let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                        task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                        let link = output_directory.join(action.relative_destination.to_string()).join(test_file.file_name().unwrap());
+                        let target = fs::read_link(&link).unwrap();
+                        assert_eq!(target.is_absolute(), kind == SymlinkKind::Absolute);
+                        assert_eq!(fs::canonicalize(&link).unwrap(), fs::canonicalize(test_file).unwrap());
+                        assert!(test_file.is_file());
+                    })
+                })
+            });
+        }
+    }
+
+    #[test]
+    fn symlink_action_task_dry_run_does_not_link_file() {
+        let action = SymlinkAction::new(Destination::parse(&(random_string() + "_destination")).unwrap(), SymlinkKind::Relative);
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                with_default_output_directory(test_directory, |output_directory| {
+                    let map_file_context = MapFileContext {
+                        source_dir: test_directory.clone(),
+                        dest_dir: output_directory.clone(),
+                        dry_run: true,
+                        ..Default::default()
+                    };
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                    assert!(!output_directory.is_dir());
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn hardlink_action_task_links_file() {
+        let action = HardlinkAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                with_default_output_directory(test_directory, |output_directory| {
+                    let map_file_context = MapFileContext {
+                        source_dir: test_directory.clone(),
+                        dest_dir: output_directory.clone(),
+                        dry_run: false,
+                        ..Default::default()
+                    };
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut Journal::default()).unwrap();
+                    let link = output_directory.join(action.relative_destination.to_string()).join(test_file.file_name().unwrap());
+                    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_file());
+                    fs::write(test_file, "changed").unwrap();
+                    assert_eq!(fs::read_to_string(&link).unwrap(), "changed");
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn copy_across_devices_follows_link_fallback() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let destination = test_directory.join(random_string() + "_copy");
+                let fail = MapFileContext { link_fallback: LinkFallback::Fail, ..Default::default() };
+                assert!(copy_across_devices(test_file, &destination, &fail, &mut Journal::default()).is_err());
+                assert!(!destination.exists());
+                let copy = MapFileContext { link_fallback: LinkFallback::Copy, ..Default::default() };
+                copy_across_devices(test_file, &destination, &copy, &mut Journal::default()).unwrap();
+                assert!(destination.is_file());
+                fs::remove_file(&destination).unwrap();
+            })
+        });
+    }
+
+    #[test]
+    fn path_between_directories() {
+        assert_eq!(path_between(Path::new("/library/views/by-author"), Path::new("/library/books/a.epub")),
+                   PathBuf::from("../../books/a.epub"));
+        assert_eq!(path_between(Path::new("/library"), Path::new("/library/books/a.epub")),
+                   PathBuf::from("books/a.epub"));
+    }
+
+    #[test]
+    fn copy_action_task_follows_conflict_policy() {
+        let action = CopyAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                with_default_output_directory(test_directory, |output_directory| {
+                    let map_file_context = MapFileContext {
+                        source_dir: test_directory.clone(),
+                        dest_dir: output_directory.clone(),
+                        dry_run: false,
+                        on_conflict: ConflictPolicy::Rename,
+                        ..Default::default()
+                    };
+                    let destination_directory = output_directory.join(action.relative_destination.to_string());
+                    let existing = destination_directory.join(test_file.file_name().unwrap());
+                    fs::create_dir_all(&destination_directory).unwrap();
+                    fs::write(&existing, "existing").unwrap();
+
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    let renamed = match task.execute(&map_file_context, &mut Journal::default()).unwrap() {
+                        Outcome::Renamed(renamed) => renamed,
+                        outcome => panic!("Expected the file to be renamed, but it was {:?}", outcome),
+                    };
+                    assert!(renamed.is_file());
+                    assert_eq!(fs::read_to_string(&existing).unwrap(), "existing");
+
+                    let skip_context = MapFileContext { on_conflict: ConflictPolicy::Skip, ..map_file_context.clone() };
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &skip_context).unwrap();
+                    assert_eq!(task.execute(&skip_context, &mut Journal::default()).unwrap(), Outcome::Skipped);
+                    assert_eq!(fs::read_to_string(&existing).unwrap(), "existing");
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn move_action_task_deletes_already_mapped_file() {
+        let action = MoveAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                with_default_output_directory(test_directory, |output_directory| {
+                    let map_file_context = MapFileContext {
+                        source_dir: test_directory.clone(),
+                        dest_dir: output_directory.clone(),
+                        dry_run: false,
+                        dedup: true,
+                        ..Default::default()
+                    };
+                    let destination_directory = output_directory.join(action.relative_destination.to_string());
+                    fs::create_dir_all(&destination_directory).unwrap();
+                    fs::copy(test_file, destination_directory.join(test_file.file_name().unwrap())).unwrap();
+
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    let mut journal = Journal::default();
+                    let duplicate = destination_directory.join(test_file.file_name().unwrap());
+                    assert_eq!(task.execute(&map_file_context, &mut journal).unwrap(), Outcome::AlreadyMapped(duplicate));
+                    assert!(test_file.exists());
+                    journal.commit().unwrap();
+                    assert!(!test_file.exists());
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn copy_action_task_roll_back_removes_copy() {
+        let action = CopyAction::new(Destination::parse(&(random_string() + "_destination/nested")).unwrap());
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                with_default_output_directory(test_directory, |output_directory| {
+                    let map_file_context = MapFileContext {
+                        source_dir: test_directory.clone(),
+                        dest_dir: output_directory.clone(),
+                        dry_run: false,
+                        ..Default::default()
+                    };
+                    let mut journal = Journal::default();
+                    let task = action.create_task(test_file.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    task.execute(&map_file_context, &mut journal).unwrap();
+                    assert!(output_directory.is_dir());
+                    journal.roll_back().unwrap();
+                    assert!(!output_directory.exists());
+                    assert!(test_file.is_file());
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn hardlink_action_task_failure_restores_overwritten_file() {
+        let action = HardlinkAction::new(Destination::parse(&(random_string() + "_destination")).unwrap());
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |_test_file| {
+                with_default_output_directory(test_directory, |output_directory| {
+                    let map_file_context = MapFileContext {
+                        source_dir: test_directory.clone(),
+                        dest_dir: output_directory.clone(),
+                        dry_run: false,
+                        on_conflict: ConflictPolicy::Overwrite,
+                        ..Default::default()
+                    };
+                    let destination_directory = output_directory.join(action.relative_destination.to_string());
+                    // Directories can't be hard linked, so linking this fails after the existing file is moved aside
+                    let directory = test_directory.join(random_string() + "_directory");
+                    let existing = destination_directory.join(directory.file_name().unwrap());
+                    fs::create_dir(&directory).unwrap();
+                    fs::create_dir_all(&destination_directory).unwrap();
+                    fs::write(&existing, "existing").unwrap();
+
+                    let task = action.create_task(directory.clone(), &TemplateVariables::new(), &map_file_context).unwrap();
+                    let mut journal = Journal::default();
+                    assert!(task.execute(&map_file_context, &mut journal).is_err());
+                    assert_eq!(fs::read_to_string(&existing).unwrap(), "existing");
+                    assert_eq!(fs::read_dir(&destination_directory).unwrap().count(), 1);
+                })
+            })
+        });
+    }
+}
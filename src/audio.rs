@@ -0,0 +1,624 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use content_type::ContentType;
+use context::MapFileContext;
+use error::*;
+use metadata::{format_operator, Operator};
+use rule::MapRule;
+
+/// Names of the tags that are read from audio files, which are also the names of their template variables.
+pub const AUDIO_TAG_NAMES: &[&str] = &["artist", "albumartist", "album", "title", "track", "disc", "year", "genre"];
+
+/// The tags of an audio file, read from ID3v2 tags in MP3s, Vorbis comments in FLAC and Ogg files and
+/// metadata atoms in MP4s.
+pub struct AudioTags {
+    tags: HashMap<&'static str, String>,
+}
+
+impl AudioTags {
+    /// Reads the tags of `file`, or returns `None` if it isn't an audio file with tags.
+    pub fn read(file: &Path) -> Result<Option<AudioTags>> {
+        let content_type = ContentType::detect(file)?;
+        let opened = File::open(file).chain_err(|| format!("Unable to open {}", file.to_string_lossy()))?;
+        let mut reader = BufReader::new(opened);
+        let mut tags = AudioTags { tags: HashMap::new() };
+        let result = match content_type {
+            Some(ContentType::Mp3) => tags.read_id3(&mut reader),
+            Some(ContentType::Flac) => tags.read_flac(&mut reader),
+            Some(ContentType::Ogg) => tags.read_ogg(&mut reader),
+            Some(ContentType::M4a) | Some(ContentType::M4b) | Some(ContentType::Mp4) => tags.read_mp4(&mut reader),
+            _ => return Ok(None),
+        };
+        result.chain_err(|| format!("Unable to read audio tags of {}", file.to_string_lossy()))?;
+
+        if tags.tags.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(tags))
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.tags.get(name).map(String::as_str)
+    }
+
+    /// Records a tag, keeping only the number of numbers such as `3/12` and the year of dates. The first
+    /// value found for a tag wins.
+    fn set(&mut self, name: &'static str, value: &str) {
+        let value = value.trim_matches(|character: char| character == '\0' || character.is_whitespace());
+        let value = match name {
+            "track" | "disc" => match value.split('/').next().and_then(|number| number.trim().parse::<u32>().ok()) {
+                Some(number) => number.to_string(),
+                None => return,
+            },
+            "year" => match value.get(..4) {
+                Some(year) if year.bytes().all(|byte| byte.is_ascii_digit()) => year.to_string(),
+                _ => return,
+            },
+            _ => value.to_string(),
+        };
+        if !value.is_empty() {
+            self.tags.entry(name).or_insert(value);
+        }
+    }
+
+    fn read_id3<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut header = vec![];
+        reader.take(10).read_to_end(&mut header)?;
+        // MP3s don't need to start with an ID3v2 tag
+        if header.len() < 10 || !header.starts_with(b"ID3") {
+            return Ok(());
+        }
+        let version = header[3];
+        let flags = header[5];
+        let mut body = read_bytes(reader, syncsafe(&header[6..10]))?;
+        if version < 4 && flags & 0x80 != 0 {
+            body = remove_unsynchronisation(&body);
+        }
+
+        let mut position = 0;
+        if version >= 3 && flags & 0x40 != 0 {
+            position = match body.get(..4) {
+                Some(size) if version == 3 => big_endian(size) + 4,
+                Some(size) => syncsafe(size),
+                None => return Ok(()),
+            };
+        }
+        let (id_length, frame_header_length) = if version == 2 { (3, 6) } else { (4, 10) };
+        while position + frame_header_length <= body.len() {
+            let frame_header = &body[position..position + frame_header_length];
+            // Frames are followed by padding
+            if frame_header[0] == 0 {
+                break;
+            }
+            let frame_size = match version {
+                2 => big_endian(&frame_header[3..6]),
+                3 => big_endian(&frame_header[4..8]),
+                _ => syncsafe(&frame_header[4..8]),
+            };
+            let start = position + frame_header_length;
+            let end = start + frame_size;
+            if end > body.len() {
+                break;
+            }
+            position = end;
+
+            let name = match id3_tag_name(&frame_header[..id_length]) {
+                Some(name) => name,
+                None => continue,
+            };
+            let mut data = body[start..end].to_vec();
+            match version {
+                // Compressed and encrypted frames are skipped
+                3 if frame_header[9] & 0xC0 != 0 => continue,
+                4 if frame_header[9] & 0x0C != 0 => continue,
+                4 => {
+                    if frame_header[9] & 0x02 != 0 {
+                        data = remove_unsynchronisation(&data);
+                    }
+                    if frame_header[9] & 0x01 != 0 {
+                        data = data.split_off(4.min(data.len()));
+                    }
+                }
+                _ => (),
+            }
+            self.set(name, &decode_id3_text(&data));
+        }
+
+        Ok(())
+    }
+
+    fn read_flac<R: Read + Seek>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        loop {
+            let mut header = [0; 4];
+            reader.read_exact(&mut header)?;
+            let is_last = header[0] & 0x80 != 0;
+            let length = big_endian(&header[1..4]);
+            if header[0] & 0x7F == 4 {
+                let block = read_bytes(reader, length)?;
+                self.read_vorbis_comment(&block);
+                return Ok(());
+            }
+            reader.seek(SeekFrom::Current(length as i64))?;
+            if is_last {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads the comment header of an Ogg Vorbis or Opus file, which is its second packet.
+    fn read_ogg<R: Read>(&mut self, reader: &mut R) -> io::Result<()> {
+        let mut packet = vec![];
+        let mut packet_count = 0;
+        loop {
+            let mut header = [0; 27];
+            reader.read_exact(&mut header)?;
+            if &header[..4] != b"OggS" {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid Ogg page"));
+            }
+            let mut segment_lengths = vec![0; header[26] as usize];
+            reader.read_exact(&mut segment_lengths)?;
+            for segment_length in segment_lengths {
+                let start = packet.len();
+                packet.resize(start + segment_length as usize, 0);
+                reader.read_exact(&mut packet[start..])?;
+                // Packets end with the first segment shorter than the maximum segment length
+                if segment_length < 255 {
+                    packet_count += 1;
+                    if packet_count == 2 {
+                        if packet.starts_with(b"\x03vorbis") {
+                            self.read_vorbis_comment(&packet[7..]);
+                        } else if packet.starts_with(b"OpusTags") {
+                            self.read_vorbis_comment(&packet[8..]);
+                        }
+                        return Ok(());
+                    }
+                    packet.clear();
+                }
+            }
+        }
+    }
+
+    fn read_vorbis_comment(&mut self, comment: &[u8]) {
+        let mut position = 0;
+        let mut next = |length: usize| {
+            let field = comment.get(position..position + length);
+            position += length;
+            field
+        };
+        let vendor_length = match next(4) {
+            Some(length) => little_endian(length),
+            None => return,
+        };
+        next(vendor_length);
+        let count = match next(4) {
+            Some(count) => little_endian(count),
+            None => return,
+        };
+        for _ in 0..count {
+            let length = match next(4) {
+                Some(length) => little_endian(length),
+                None => return,
+            };
+            let field = match next(length) {
+                Some(field) => String::from_utf8_lossy(field).into_owned(),
+                None => return,
+            };
+            let mut parts = field.splitn(2, '=');
+            let key = parts.next().unwrap_or_default().to_uppercase();
+            let name = match key.as_str() {
+                "ARTIST" => "artist",
+                "ALBUMARTIST" | "ALBUM ARTIST" => "albumartist",
+                "ALBUM" => "album",
+                "TITLE" => "title",
+                "TRACKNUMBER" => "track",
+                "DISCNUMBER" => "disc",
+                "DATE" | "YEAR" => "year",
+                "GENRE" => "genre",
+                _ => continue,
+            };
+            self.set(name, parts.next().unwrap_or_default());
+        }
+    }
+
+    /// Reads the `moov/udta/meta/ilst` atom of an MP4 file, skipping over the media data.
+    fn read_mp4<R: Read + Seek>(&mut self, reader: &mut R) -> io::Result<()> {
+        loop {
+            let mut header = [0; 8];
+            match reader.read_exact(&mut header) {
+                Err(ref error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                result => result?,
+            }
+            let (size, header_length) = match big_endian(&header[..4]) {
+                // Atoms with a size of 0 extend to the end of the file and so can't be followed by moov
+                0 => return Ok(()),
+                1 => {
+                    let mut extended_size = [0; 8];
+                    reader.read_exact(&mut extended_size)?;
+                    (big_endian(&extended_size), 16)
+                }
+                size => (size, 8),
+            };
+            if size < header_length {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid MP4 atom size"));
+            }
+            if &header[4..] == b"moov" {
+                let moov = read_bytes(reader, size - header_length)?;
+                self.read_mp4_moov(&moov);
+                return Ok(());
+            }
+            let skipped = i64::try_from(size - header_length)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid MP4 atom size"))?;
+            reader.seek(SeekFrom::Current(skipped))?;
+        }
+    }
+
+    fn read_mp4_moov(&mut self, moov: &[u8]) {
+        let meta = match find_atom(moov, b"udta").and_then(|udta| find_atom(udta, b"meta")) {
+            Some(meta) => meta,
+            None => return,
+        };
+        // meta is usually followed by a version and flags, but not in QuickTime files
+        let meta_children = if meta.get(4..8) == Some(b"hdlr") { meta } else { &meta[4.min(meta.len())..] };
+        let items = match find_atom(meta_children, b"ilst") {
+            Some(items) => items,
+            None => return,
+        };
+        for (kind, item) in atoms(items) {
+            let name = match &kind {
+                b"\xA9ART" => "artist",
+                b"aART" => "albumartist",
+                b"\xA9alb" => "album",
+                b"\xA9nam" => "title",
+                b"trkn" => "track",
+                b"disk" => "disc",
+                b"\xA9day" => "year",
+                b"\xA9gen" => "genre",
+                _ => continue,
+            };
+            // data atoms start with a type and a locale
+            let value = match find_atom(item, b"data").and_then(|data| data.get(8..)) {
+                Some(value) => value,
+                None => continue,
+            };
+            match name {
+                "track" | "disc" => {
+                    if let Some(number) = value.get(2..4) {
+                        self.set(name, &big_endian(number).to_string());
+                    }
+                }
+                _ => self.set(name, &String::from_utf8_lossy(value)),
+            }
+        }
+    }
+}
+
+/// Matches audio files with tags, e.g. `audio`, or with a particular tag, e.g. `audio:album`.
+pub struct AudioRule {
+    tag: Option<&'static str>,
+}
+
+impl AudioRule {
+    pub fn parse(operator: Option<Operator>, value: &str) -> Result<AudioRule> {
+        let tag = match operator {
+            None => None,
+            Some(Operator::Colon) => match AUDIO_TAG_NAMES.iter().find(|&&name| name == value) {
+                Some(&name) => Some(name),
+                None => bail!("Unknown audio tag {}. Known tags are {}", value, AUDIO_TAG_NAMES.join(", ")),
+            },
+            operator => bail!("Expected audio or audio:<tag>, found audio{}", format_operator(operator)),
+        };
+
+        Ok(AudioRule { tag })
+    }
+}
+
+impl MapRule for AudioRule {
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        match AudioTags::read(file) {
+            Ok(Some(tags)) => self.tag.is_none_or(|tag| tags.get(tag).is_some()),
+            Ok(None) => false,
+            Err(error) => {
+                warn!("{}", error);
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Debug for AudioRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tag {
+            Some(tag) => write!(f, "audio:{}", tag),
+            None => write!(f, "audio"),
+        }
+    }
+}
+
+fn id3_tag_name(frame_id: &[u8]) -> Option<&'static str> {
+    let name = match frame_id {
+        b"TPE1" | b"TP1" => "artist",
+        b"TPE2" | b"TP2" => "albumartist",
+        b"TALB" | b"TAL" => "album",
+        b"TIT2" | b"TT2" => "title",
+        b"TRCK" | b"TRK" => "track",
+        b"TPOS" | b"TPA" => "disc",
+        b"TDRC" | b"TYER" | b"TYE" => "year",
+        b"TCON" | b"TCO" => "genre",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+/// Decodes the first string of an ID3v2 text frame, which starts with a byte giving its encoding.
+fn decode_id3_text(data: &[u8]) -> String {
+    let (encoding, text) = match data.split_first() {
+        Some((&encoding, text)) => (encoding, text),
+        None => return String::new(),
+    };
+    match encoding {
+        0 => text.iter().take_while(|&&byte| byte != 0).map(|&byte| char::from(byte)).collect(),
+        1 | 2 => {
+            let little_endian = match text.get(..2) {
+                Some(b"\xFF\xFE") => true,
+                Some(b"\xFE\xFF") => false,
+                _ => encoding == 1,
+            };
+            let has_byte_order_mark = encoding == 1 || text.starts_with(b"\xFE\xFF");
+            let text = if has_byte_order_mark && text.len() >= 2 { &text[2..] } else { text };
+            let units: Vec<u16> = text
+                .chunks(2)
+                .filter(|unit| unit.len() == 2)
+                .map(|unit| if little_endian { u16::from(unit[1]) << 8 | u16::from(unit[0]) } else { u16::from(unit[0]) << 8 | u16::from(unit[1]) })
+                .take_while(|&unit| unit != 0)
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => {
+            let end = text.iter().position(|&byte| byte == 0).unwrap_or(text.len());
+            String::from_utf8_lossy(&text[..end]).into_owned()
+        }
+    }
+}
+
+/// Undoes ID3v2 "unsynchronisation", which inserts a zero byte after every 0xFF.
+fn remove_unsynchronisation(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut previous = 0;
+    for &byte in data {
+        if !(previous == 0xFF && byte == 0) {
+            result.push(byte);
+        }
+        previous = byte;
+    }
+
+    result
+}
+
+/// The child atoms of an MP4 atom, as their kind and their content. Those after one whose size runs past the end of
+/// `data` are left out.
+fn atoms(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut atoms = vec![];
+    let mut position = 0;
+    while position + 8 <= data.len() {
+        let mut kind = [0; 4];
+        kind.copy_from_slice(&data[position + 4..position + 8]);
+        let (size, header_length) = match big_endian(&data[position..position + 4]) {
+            0 => (data.len() - position, 8),
+            1 => match data.get(position + 8..position + 16) {
+                Some(size) => (big_endian(size), 16),
+                None => break,
+            },
+            size => (size, 8),
+        };
+        if size < header_length || size > data.len() - position {
+            break;
+        }
+        atoms.push((kind, &data[position + header_length..position + size]));
+        position += size;
+    }
+
+    atoms
+}
+
+fn find_atom<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    atoms(data).into_iter().find(|(atom_kind, _)| atom_kind == kind).map(|(_, content)| content)
+}
+
+/// Reads `length` bytes, which come from the file itself and so are only allocated as they're read, so that a
+/// corrupt length can't exhaust memory.
+fn read_bytes<R: Read>(reader: &mut R, length: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![];
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "File is shorter than the size it gives"));
+    }
+    Ok(bytes)
+}
+
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |value, &byte| value << 8 | byte as usize)
+}
+
+fn little_endian(bytes: &[u8]) -> usize {
+    bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as usize)
+}
+
+/// ID3v2 sizes use 7 bits of each byte, so that they can't be mistaken for an MPEG frame sync.
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |value, &byte| value << 7 | (byte & 0x7F) as usize)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use testutils::*;
+
+    fn read_tags(content: &[u8]) -> Option<AudioTags> {
+        try_read_tags(content).unwrap()
+    }
+
+    fn try_read_tags(content: &[u8]) -> Result<Option<AudioTags>> {
+        let tags = RefCell::new(Ok(None));
+        with_default_test_directory(|test_directory| {
+            let file = test_directory.join("audio");
+            File::create(&file).unwrap().write_all(content).unwrap();
+            *tags.borrow_mut() = AudioTags::read(&file);
+        });
+        tags.into_inner()
+    }
+
+    #[test]
+    fn audio_tags_from_id3v23() {
+        let tags = read_tags(&mp3_with_id3()).unwrap();
+        assert_eq!(tags.get("artist"), Some("Daft Punk"));
+        assert_eq!(tags.get("album"), Some("Discovery"));
+        assert_eq!(tags.get("title"), Some("Digital Love"));
+        assert_eq!(tags.get("track"), Some("3"));
+        assert_eq!(tags.get("genre"), None);
+    }
+
+    #[test]
+    fn audio_tags_from_id3v24_utf16() {
+        let mut frame = vec![1, 0xFF, 0xFE];
+        for unit in "Björk".encode_utf16() {
+            frame.extend_from_slice(&[unit as u8, (unit >> 8) as u8]);
+        }
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend_from_slice(&[0, 0, 0, 10 + frame.len() as u8]);
+        tag.extend_from_slice(b"TPE1");
+        tag.extend_from_slice(&[0, 0, 0, frame.len() as u8, 0, 0]);
+        tag.extend_from_slice(&frame);
+        let tags = read_tags(&tag).unwrap();
+        assert_eq!(tags.get("artist"), Some("Björk"));
+    }
+
+    #[test]
+    fn audio_tags_from_flac() {
+        let comment = vorbis_comment(&["ARTIST=Nina Simone", "Album=Pastel Blues", "TRACKNUMBER=07", "DATE=1965-10-01"]);
+        let mut flac = b"fLaC".to_vec();
+        // A STREAMINFO block, which is skipped, then the VORBIS_COMMENT block
+        flac.extend_from_slice(&[0x00, 0, 0, 34]);
+        flac.extend_from_slice(&[0; 34]);
+        flac.extend_from_slice(&[0x84, 0, 0, comment.len() as u8]);
+        flac.extend_from_slice(&comment);
+        let tags = read_tags(&flac).unwrap();
+        assert_eq!(tags.get("artist"), Some("Nina Simone"));
+        assert_eq!(tags.get("album"), Some("Pastel Blues"));
+        assert_eq!(tags.get("track"), Some("7"));
+        assert_eq!(tags.get("year"), Some("1965"));
+    }
+
+    #[test]
+    fn audio_tags_from_ogg_vorbis() {
+        let mut comment = b"\x03vorbis".to_vec();
+        comment.extend_from_slice(&vorbis_comment(&["TITLE=Sinnerman"]));
+        let mut ogg = ogg_page(b"\x01vorbis");
+        ogg.extend_from_slice(&ogg_page(&comment));
+        let tags = read_tags(&ogg).unwrap();
+        assert_eq!(tags.get("title"), Some("Sinnerman"));
+    }
+
+    #[test]
+    fn audio_tags_from_mp4() {
+        let text_item = |kind: &[u8], text: &[u8]| {
+            let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+            data.extend_from_slice(text);
+            atom(kind, &atom(b"data", &data))
+        };
+        let mut items = text_item(b"\xA9ART", b"Radiohead");
+        items.extend_from_slice(&atom(b"trkn", &atom(b"data", &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 10, 0, 0])));
+        let mut meta = vec![0, 0, 0, 0];
+        meta.extend_from_slice(&atom(b"hdlr", &[0; 25]));
+        meta.extend_from_slice(&atom(b"ilst", &items));
+        let mut mp4 = atom(b"ftyp", b"M4A \0\0\0\0");
+        mp4.extend_from_slice(&atom(b"mdat", &[0; 64]));
+        mp4.extend_from_slice(&atom(b"moov", &atom(b"udta", &atom(b"meta", &meta))));
+        let tags = read_tags(&mp4).unwrap();
+        assert_eq!(tags.get("artist"), Some("Radiohead"));
+        assert_eq!(tags.get("track"), Some("2"));
+    }
+
+    #[test]
+    fn audio_tags_with_corrupt_sizes() {
+        let mut mp4 = atom(b"ftyp", b"M4A \0\0\0\0");
+        mp4.extend_from_slice(b"\0\0\0\x01moov\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF");
+        assert!(try_read_tags(&mp4).is_err());
+        let mut mp4 = atom(b"ftyp", b"M4A \0\0\0\0");
+        mp4.extend_from_slice(b"\0\0\0\x01mdat\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF");
+        assert!(try_read_tags(&mp4).is_err());
+        assert!(try_read_tags(b"ID3\x03\0\0\x7F\x7F\x7F\x7F").is_err());
+        assert!(try_read_tags(b"fLaC\x84\xFF\xFF\xFF").is_err());
+    }
+
+    #[test]
+    fn audio_tags_with_huge_extended_atom_size() {
+        let mut moov = atom(b"mvhd", &[0; 4]);
+        moov.extend_from_slice(b"\0\0\0\x01udta\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF");
+        moov.extend_from_slice(&atom(b"meta", &[0; 4]));
+        let mut mp4 = atom(b"ftyp", b"M4A \0\0\0\0");
+        mp4.extend_from_slice(&atom(b"moov", &moov));
+        assert!(read_tags(&mp4).is_none());
+    }
+
+    #[test]
+    fn audio_tags_of_other_files() {
+        assert!(read_tags(b"%PDF-1.4\n").is_none());
+        assert!(read_tags(b"\xFF\xFB\x90\x64").is_none());
+    }
+
+    #[test]
+    fn audio_rule_matches_tags() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                File::create(test_file).unwrap().write_all(&mp3_with_id3()).unwrap();
+                let file_context = dummy_map_file_context();
+                assert!(AudioRule::parse(None, "").unwrap().file_matches_rule(test_file, &file_context));
+                assert!(AudioRule::parse(Some(Operator::Colon), "album").unwrap().file_matches_rule(test_file, &file_context));
+                assert!(!AudioRule::parse(Some(Operator::Colon), "genre").unwrap().file_matches_rule(test_file, &file_context));
+            })
+        });
+    }
+
+    #[test]
+    fn audio_rule_unknown_tag() {
+        assert!(AudioRule::parse(Some(Operator::Colon), "composer").is_err());
+    }
+
+    fn vorbis_comment(fields: &[&str]) -> Vec<u8> {
+        let mut comment = vec![4, 0, 0, 0];
+        comment.extend_from_slice(b"test");
+        comment.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        for field in fields {
+            comment.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            comment.extend_from_slice(field.as_bytes());
+        }
+        comment
+    }
+
+    fn ogg_page(packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0; 22]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    fn atom(kind: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut atom = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(content);
+        atom
+    }
+}
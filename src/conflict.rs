@@ -0,0 +1,184 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use context::{ConflictPolicy, MapFileContext};
+use error::*;
+use hash::same_contents;
+use summary::Outcome;
+
+/// Decides what to do with `file` if `destination` already exists, logging the decision. With `--dedup`, a
+/// destination with the same contents is `Outcome::AlreadyMapped`, otherwise `--on-conflict` decides.
+/// Returns `Outcome::Mapped` if there's no conflict.
+pub fn resolve_conflict(file_context: &MapFileContext, file: &Path, destination: &Path) -> Result<Outcome> {
+    let destination_metadata = match fs::symlink_metadata(destination) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(Outcome::Mapped),
+    };
+    if is_same_file(file, destination) {
+        info!("Skipping {}, as it's already at its destination", file.to_string_lossy());
+        return Ok(Outcome::Skipped);
+    }
+    if file_context.dedup && destination_metadata.is_file() {
+        let duplicate = same_contents(file, destination, file_context.hash_algorithm)
+            .chain_err(|| format!("Unable to compare {} with {}", file.to_string_lossy(),
+                                  destination.to_string_lossy()))?;
+        if duplicate {
+            info!("{} is already mapped, as {} has the same contents", file.to_string_lossy(),
+                  destination.to_string_lossy());
+            return Ok(Outcome::AlreadyMapped(destination.to_path_buf()));
+        }
+    }
+    let file_metadata = fs::metadata(file)
+        .chain_err(|| format!("Unable to read metadata of {}", file.to_string_lossy()))?;
+
+    let overwrite = match file_context.on_conflict {
+        ConflictPolicy::Skip => false,
+        ConflictPolicy::Fail => bail!("Unable to map {}, as {} already exists. Use --on-conflict to choose what to do \
+                                       with existing files", file.to_string_lossy(), destination.to_string_lossy()),
+        ConflictPolicy::Overwrite => true,
+        ConflictPolicy::Rename => {
+            let renamed = free_path(destination);
+            info!("{} already exists, so mapping {} to {}", destination.to_string_lossy(), file.to_string_lossy(),
+                  renamed.to_string_lossy());
+            return Ok(Outcome::Renamed(renamed));
+        }
+        ConflictPolicy::Newer => match (file_metadata.modified(), destination_metadata.modified()) {
+            (Ok(file_time), Ok(destination_time)) => file_time > destination_time,
+            _ => bail!("Unable to compare modification times of {} and {}", file.to_string_lossy(),
+                       destination.to_string_lossy()),
+        },
+        ConflictPolicy::Larger => file_metadata.len() > destination_metadata.len(),
+        ConflictPolicy::IdenticalSkip => {
+            let identical = same_contents(file, destination, file_context.hash_algorithm)
+                .chain_err(|| format!("Unable to compare {} with {}", file.to_string_lossy(),
+                                      destination.to_string_lossy()))?;
+            if !identical {
+                bail!("Unable to map {}, as {} already exists with different contents", file.to_string_lossy(),
+                      destination.to_string_lossy())
+            }
+            false
+        }
+    };
+
+    if overwrite {
+        info!("Overwriting {} with {}", destination.to_string_lossy(), file.to_string_lossy());
+        Ok(Outcome::Overwritten)
+    } else {
+        info!("Skipping {}, as {} already exists", file.to_string_lossy(), destination.to_string_lossy());
+        Ok(Outcome::Skipped)
+    }
+}
+
+fn is_same_file(file: &Path, destination: &Path) -> bool {
+    match (fs::canonicalize(file), fs::canonicalize(destination)) {
+        (Ok(file), Ok(destination)) => file == destination,
+        _ => false,
+    }
+}
+
+/// The first of `name (1).ext`, `name (2).ext` and so on that doesn't exist beside `destination`.
+fn free_path(destination: &Path) -> PathBuf {
+    let stem = destination.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let extension = destination.extension().map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|number| destination.with_file_name(format!("{} ({}){}", stem, number, extension)))
+        .find(|path| fs::symlink_metadata(path).is_err())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use testutils::*;
+
+    fn policy(on_conflict: ConflictPolicy) -> MapFileContext {
+        MapFileContext { on_conflict, ..Default::default() }
+    }
+
+    fn with_conflicting_files<F>(test_method: F) where F: Fn(&PathBuf, &PathBuf) {
+        with_default_test_directory(|test_directory| {
+            let file = test_directory.join("file.txt");
+            let destination = test_directory.join("destination.txt");
+            fs::write(&file, "new contents").unwrap();
+            fs::write(&destination, "old").unwrap();
+            test_method(&file, &destination);
+        });
+    }
+
+    #[test]
+    fn resolve_conflict_without_conflict() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let destination = test_directory.join("missing.txt");
+                let outcome = resolve_conflict(&policy(ConflictPolicy::Fail), test_file, &destination).unwrap();
+                assert_eq!(outcome, Outcome::Mapped);
+            })
+        });
+    }
+
+    #[test]
+    fn resolve_conflict_policies() {
+        with_conflicting_files(|file, destination| {
+            assert_eq!(resolve_conflict(&policy(ConflictPolicy::Skip), file, destination).unwrap(), Outcome::Skipped);
+            assert!(resolve_conflict(&policy(ConflictPolicy::Fail), file, destination).is_err());
+            let overwrite = resolve_conflict(&policy(ConflictPolicy::Overwrite), file, destination).unwrap();
+            assert_eq!(overwrite, Outcome::Overwritten);
+            let larger = resolve_conflict(&policy(ConflictPolicy::Larger), file, destination).unwrap();
+            assert_eq!(larger, Outcome::Overwritten);
+            assert!(resolve_conflict(&policy(ConflictPolicy::IdenticalSkip), file, destination).is_err());
+        });
+    }
+
+    #[test]
+    fn resolve_conflict_newer() {
+        with_conflicting_files(|file, destination| {
+            let past = SystemTime::now() - Duration::from_secs(3600);
+            fs::File::options().write(true).open(file).unwrap().set_modified(past).unwrap();
+            let older = resolve_conflict(&policy(ConflictPolicy::Newer), file, destination).unwrap();
+            assert_eq!(older, Outcome::Skipped);
+            fs::File::options().write(true).open(destination).unwrap()
+                .set_modified(past - Duration::from_secs(3600)).unwrap();
+            let newer = resolve_conflict(&policy(ConflictPolicy::Newer), file, destination).unwrap();
+            assert_eq!(newer, Outcome::Overwritten);
+        });
+    }
+
+    #[test]
+    fn resolve_conflict_identical_skip() {
+        with_conflicting_files(|file, destination| {
+            fs::write(destination, "new contents").unwrap();
+            let outcome = resolve_conflict(&policy(ConflictPolicy::IdenticalSkip), file, destination).unwrap();
+            assert_eq!(outcome, Outcome::Skipped);
+        });
+    }
+
+    #[test]
+    fn resolve_conflict_rename_finds_free_name() {
+        with_conflicting_files(|file, destination| {
+            let directory = destination.parent().unwrap();
+            fs::write(directory.join("destination (1).txt"), "").unwrap();
+            assert_eq!(resolve_conflict(&policy(ConflictPolicy::Rename), file, destination).unwrap(),
+                       Outcome::Renamed(directory.join("destination (2).txt")));
+        });
+    }
+
+    #[test]
+    fn resolve_conflict_same_file() {
+        with_conflicting_files(|file, _destination| {
+            assert_eq!(resolve_conflict(&policy(ConflictPolicy::Overwrite), file, file).unwrap(), Outcome::Skipped);
+        });
+    }
+
+    #[test]
+    fn resolve_conflict_dedup() {
+        with_conflicting_files(|file, destination| {
+            let file_context = MapFileContext { dedup: true, ..policy(ConflictPolicy::Fail) };
+            assert!(resolve_conflict(&file_context, file, destination).is_err());
+            fs::write(destination, "new contents").unwrap();
+            assert_eq!(resolve_conflict(&file_context, file, destination).unwrap(),
+                       Outcome::AlreadyMapped(destination.clone()));
+        });
+    }
+}
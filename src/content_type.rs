@@ -0,0 +1,309 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use context::MapFileContext;
+use error::*;
+use metadata::{format_operator, Operator};
+use rule::MapRule;
+
+/// How many bytes from the start of a file are read to identify its type.
+const SNIFF_LENGTH: u64 = 512;
+
+/// The kind of a file's content, identified by the "magic bytes" at its start rather than its extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentType {
+    Pdf,
+    Epub,
+    Mobi,
+    Djvu,
+    Zip,
+    SevenZip,
+    Rar,
+    Gzip,
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Tiff,
+    Mp3,
+    Flac,
+    Ogg,
+    Wav,
+    M4a,
+    M4b,
+    Mp4,
+    Mkv,
+    Webm,
+    Avi,
+}
+
+const CONTENT_TYPES: &[ContentType] = &[
+    ContentType::Pdf,
+    ContentType::Epub,
+    ContentType::Mobi,
+    ContentType::Djvu,
+    ContentType::Zip,
+    ContentType::SevenZip,
+    ContentType::Rar,
+    ContentType::Gzip,
+    ContentType::Png,
+    ContentType::Jpeg,
+    ContentType::Gif,
+    ContentType::Webp,
+    ContentType::Tiff,
+    ContentType::Mp3,
+    ContentType::Flac,
+    ContentType::Ogg,
+    ContentType::Wav,
+    ContentType::M4a,
+    ContentType::M4b,
+    ContentType::Mp4,
+    ContentType::Mkv,
+    ContentType::Webm,
+    ContentType::Avi,
+];
+
+impl ContentType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentType::Pdf => "pdf",
+            ContentType::Epub => "epub",
+            ContentType::Mobi => "mobi",
+            ContentType::Djvu => "djvu",
+            ContentType::Zip => "zip",
+            ContentType::SevenZip => "7z",
+            ContentType::Rar => "rar",
+            ContentType::Gzip => "gz",
+            ContentType::Png => "png",
+            ContentType::Jpeg => "jpeg",
+            ContentType::Gif => "gif",
+            ContentType::Webp => "webp",
+            ContentType::Tiff => "tiff",
+            ContentType::Mp3 => "mp3",
+            ContentType::Flac => "flac",
+            ContentType::Ogg => "ogg",
+            ContentType::Wav => "wav",
+            ContentType::M4a => "m4a",
+            ContentType::M4b => "m4b",
+            ContentType::Mp4 => "mp4",
+            ContentType::Mkv => "mkv",
+            ContentType::Webm => "webm",
+            ContentType::Avi => "avi",
+        }
+    }
+
+    /// Looks up a type by its name, also accepting common alternative extensions such as `jpg`.
+    pub fn from_name(name: &str) -> Option<ContentType> {
+        let name = name.to_lowercase();
+        let canonical_name = match name.as_str() {
+            "jpg" => "jpeg",
+            "tif" => "tiff",
+            "gzip" => "gz",
+            "azw" | "prc" => "mobi",
+            other => other,
+        };
+        CONTENT_TYPES.iter().cloned().find(|content_type| content_type.name() == canonical_name)
+    }
+
+    /// Identifies the type of `file` from its first bytes, or `None` if it isn't a known type.
+    pub fn detect(file: &Path) -> Result<Option<ContentType>> {
+        let mut header = Vec::new();
+        File::open(file)
+            .and_then(|opened| opened.take(SNIFF_LENGTH).read_to_end(&mut header))
+            .chain_err(|| format!("Unable to read content of {}", file.to_string_lossy()))?;
+
+        Ok(ContentType::from_header(&header))
+    }
+
+    fn from_header(header: &[u8]) -> Option<ContentType> {
+        let at = |offset: usize, magic: &[u8]| header.len() >= offset + magic.len() && &header[offset..offset + magic.len()] == magic;
+        let content_type = if at(0, b"%PDF-") {
+            ContentType::Pdf
+        } else if at(0, b"PK\x03\x04") {
+            // EPUBs are zip files whose first entry is an uncompressed file named "mimetype".
+            if at(30, b"mimetypeapplication/epub+zip") {
+                ContentType::Epub
+            } else {
+                ContentType::Zip
+            }
+        } else if at(0, b"PK\x05\x06") {
+            ContentType::Zip
+        } else if at(60, b"BOOKMOBI") || at(60, b"TEXtREAd") {
+            ContentType::Mobi
+        } else if at(0, b"AT&TFORM") {
+            ContentType::Djvu
+        } else if at(0, b"7z\xBC\xAF\x27\x1C") {
+            ContentType::SevenZip
+        } else if at(0, b"Rar!\x1A\x07") {
+            ContentType::Rar
+        } else if at(0, b"\x1F\x8B") {
+            ContentType::Gzip
+        } else if at(0, b"\x89PNG\r\n\x1A\n") {
+            ContentType::Png
+        } else if at(0, b"\xFF\xD8\xFF") {
+            ContentType::Jpeg
+        } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+            ContentType::Gif
+        } else if at(0, b"II*\x00") || at(0, b"MM\x00*") {
+            ContentType::Tiff
+        } else if at(0, b"RIFF") && at(8, b"WEBP") {
+            ContentType::Webp
+        } else if at(0, b"RIFF") && at(8, b"WAVE") {
+            ContentType::Wav
+        } else if at(0, b"RIFF") && at(8, b"AVI ") {
+            ContentType::Avi
+        } else if at(0, b"ID3") || is_mpeg_audio_frame(header) {
+            ContentType::Mp3
+        } else if at(0, b"fLaC") {
+            ContentType::Flac
+        } else if at(0, b"OggS") {
+            ContentType::Ogg
+        } else if at(4, b"ftyp") {
+            if at(8, b"M4A ") {
+                ContentType::M4a
+            } else if at(8, b"M4B ") {
+                ContentType::M4b
+            } else {
+                ContentType::Mp4
+            }
+        } else if at(0, b"\x1A\x45\xDF\xA3") {
+            // Matroska and WebM share a container and differ in the document type stored in its header.
+            if contains(header, b"webm") {
+                ContentType::Webm
+            } else {
+                ContentType::Mkv
+            }
+        } else {
+            return None;
+        };
+
+        Some(content_type)
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// MP3 files without an ID3 tag start straight away with an MPEG audio frame, whose 11 bit sync word is
+/// followed by a version and a layer that mustn't be the reserved value.
+fn is_mpeg_audio_frame(header: &[u8]) -> bool {
+    header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0 && header[1] & 0x06 != 0
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// Matches files whose content is of one of the given types, e.g. `type:pdf` or `type:epub|mobi`.
+pub struct ContentTypeRule {
+    content_types: Vec<ContentType>,
+}
+
+impl ContentTypeRule {
+    pub fn parse(operator: Option<Operator>, value: &str) -> Result<ContentTypeRule> {
+        if operator != Some(Operator::Colon) {
+            bail!("Expected type:<type>, found type{}", format_operator(operator));
+        }
+        let mut content_types = vec![];
+        for name in value.split('|') {
+            match ContentType::from_name(name) {
+                Some(content_type) => content_types.push(content_type),
+                None => bail!(
+                    "Unknown type {}. Known types are {}",
+                    name,
+                    CONTENT_TYPES.iter().map(|content_type| content_type.name()).collect::<Vec<&str>>().join(", ")
+                ),
+            }
+        }
+
+        Ok(ContentTypeRule { content_types })
+    }
+}
+
+impl MapRule for ContentTypeRule {
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        match ContentType::detect(file) {
+            Ok(Some(content_type)) => self.content_types.contains(&content_type),
+            Ok(None) => false,
+            Err(error) => {
+                warn!("{}", error);
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ContentTypeRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = self.content_types.iter().map(|content_type| content_type.name()).collect();
+        write!(f, "type:{}", names.join("|"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use testutils::*;
+
+    #[test]
+    fn from_header_identifies_types() {
+        assert_eq!(ContentType::from_header(b"%PDF-1.7\n"), Some(ContentType::Pdf));
+        assert_eq!(ContentType::from_header(b"\xFF\xD8\xFF\xE0\x00\x10JFIF"), Some(ContentType::Jpeg));
+        assert_eq!(ContentType::from_header(b"ID3\x04\x00"), Some(ContentType::Mp3));
+        assert_eq!(ContentType::from_header(b"\xFF\xFB\x90\x64"), Some(ContentType::Mp3));
+        assert_eq!(ContentType::from_header(b"fLaC\x00\x00\x00\x22"), Some(ContentType::Flac));
+        assert_eq!(ContentType::from_header(b"\x00\x00\x00\x20ftypM4B \x00"), Some(ContentType::M4b));
+        assert_eq!(ContentType::from_header(b"\x1A\x45\xDF\xA3\x9F\x42\x82\x88matroska"), Some(ContentType::Mkv));
+        assert_eq!(ContentType::from_header(b"plain text"), None);
+        assert_eq!(ContentType::from_header(b""), None);
+    }
+
+    #[test]
+    fn from_header_distinguishes_epub_from_zip() {
+        let mut epub = b"PK\x03\x04".to_vec();
+        epub.extend_from_slice(&[0; 26]);
+        epub.extend_from_slice(b"mimetypeapplication/epub+zip");
+        assert_eq!(ContentType::from_header(&epub), Some(ContentType::Epub));
+        assert_eq!(ContentType::from_header(&epub[..40]), Some(ContentType::Zip));
+    }
+
+    #[test]
+    fn from_header_identifies_mobi() {
+        let mut mobi = vec![0; 60];
+        mobi.extend_from_slice(b"BOOKMOBI");
+        assert_eq!(ContentType::from_header(&mobi), Some(ContentType::Mobi));
+    }
+
+    #[test]
+    fn from_name_accepts_aliases() {
+        assert_eq!(ContentType::from_name("PDF"), Some(ContentType::Pdf));
+        assert_eq!(ContentType::from_name("jpg"), Some(ContentType::Jpeg));
+        assert_eq!(ContentType::from_name("docx"), None);
+    }
+
+    #[test]
+    fn content_type_rule_sniffs_file_content() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                File::create(test_file).unwrap().write_all(b"%PDF-1.4\n").unwrap();
+                let file_context = dummy_map_file_context();
+                let books = ContentTypeRule::parse(Some(Operator::Colon), "epub|pdf").unwrap();
+                let images = ContentTypeRule::parse(Some(Operator::Colon), "png").unwrap();
+                assert!(books.file_matches_rule(test_file, &file_context));
+                assert!(!images.file_matches_rule(test_file, &file_context));
+            })
+        });
+    }
+
+    #[test]
+    fn content_type_rule_unknown_type() {
+        assert!(ContentTypeRule::parse(Some(Operator::Colon), "pdf|docx").is_err());
+        assert!(ContentTypeRule::parse(None, "pdf").is_err());
+    }
+}
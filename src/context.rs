@@ -1,8 +1,144 @@
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct MapFileContext {
     pub source_dir: PathBuf,
     pub dest_dir: PathBuf,
-    pub dry_run: bool
-}
\ No newline at end of file
+    pub dry_run: bool,
+    pub match_target: MatchTarget,
+    pub time_zone: TimeZone,
+    pub link_fallback: LinkFallback,
+    pub copy_mode: CopyMode,
+    pub on_conflict: ConflictPolicy,
+    pub rule_conflicts: RuleConflictPolicy,
+    pub dedup: bool,
+    pub hash_algorithm: HashAlgorithm,
+    pub verify: bool,
+    pub preserve: Preserve
+}
+
+/// What part of a file's path rules are matched against.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MatchTarget {
+    #[default]
+    FileName,
+    RelativePath
+}
+
+/// What a hard link action does when the destination is on a different device to the file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LinkFallback {
+    #[default]
+    Fail,
+    Copy
+}
+
+/// How files are copied. Reflinks share the file's data until either copy is changed, which is much faster
+/// and saves space on filesystems that support them, such as Btrfs and XFS.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CopyMode {
+    /// Use a reflink if possible, falling back to a full copy.
+    #[default]
+    Auto,
+    /// Only use reflinks, failing if one can't be made.
+    Reflink,
+    /// Always copy the data in full.
+    Full
+}
+
+/// What is done when a file is mapped to a destination that already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ConflictPolicy {
+    /// Leave both files alone.
+    Skip,
+    /// Stop with an error.
+    Fail,
+    /// Replace the existing file, as files always were before there was a choice.
+    #[default]
+    Overwrite,
+    /// Map the file to `name (1).ext`, or the first such name that's free.
+    Rename,
+    /// Replace the existing file if the file being mapped was modified more recently, otherwise skip.
+    Newer,
+    /// Replace the existing file if the file being mapped is larger, otherwise skip.
+    Larger,
+    /// Skip if the existing file has the same contents, otherwise stop with an error.
+    IdenticalSkip
+}
+
+/// What is done with a file that matches more than one rule.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RuleConflictPolicy {
+    /// Stop before anything is mapped, reporting every file that does.
+    #[default]
+    Error,
+    /// Apply only the rule that comes first.
+    FirstWins,
+    /// Apply only the rule that comes last.
+    LastWins,
+    /// Apply every rule, moving the file after any others.
+    All
+}
+
+/// The hash used to tell whether files have the same contents.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256
+}
+
+/// Attributes of the original file that copies are given, besides its read, write and execute permissions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Preserve {
+    /// The access and modification times.
+    pub times: bool,
+    /// The setuid, setgid and sticky bits.
+    pub mode: bool,
+    /// The owning user and group.
+    pub owner: bool,
+    /// Extended attributes, other than ACLs.
+    pub xattr: bool,
+    /// POSIX access control lists.
+    pub acl: bool
+}
+
+/// The time zone that times in destination templates are written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimeZone {
+    #[default]
+    Local,
+    Utc
+}
+
+impl MapFileContext {
+    /// The path of `file` relative to the source directory, using `/` as the separator.
+    pub fn relative_path(&self, file: &Path) -> String {
+        relative_path(&self.source_dir, file)
+    }
+
+    /// The text rules match `file` against, as chosen by `match_target`.
+    pub fn match_text(&self, file: &Path) -> String {
+        match self.match_target {
+            MatchTarget::FileName => file.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            MatchTarget::RelativePath => self.relative_path(file)
+        }
+    }
+}
+
+/// Whether `path`, relative to a directory, stays inside it. Absolute paths and those with `..` may not.
+pub fn stays_inside(path: &Path) -> bool {
+    path.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// The path of `file` relative to `directory`, using `/` as the separator. Files outside of `directory`
+/// are returned as-is.
+pub fn relative_path(directory: &Path, file: &Path) -> String {
+    let relative_path = file.strip_prefix(directory).unwrap_or(file);
+    relative_path.components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<String>>()
+        .join("/")
+}
@@ -0,0 +1,295 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use context::{CopyMode, HashAlgorithm, MapFileContext};
+use error::*;
+use hash::same_contents;
+use preserve::preserve_attributes;
+
+/// How a file was copied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CopyStrategy {
+    /// The copy shares the file's data until either is changed, as on Btrfs and XFS.
+    Reflink,
+    /// The kernel copied the data without it passing through this process, which some filesystems do by
+    /// sharing it.
+    CopyFileRange,
+    /// The data was read and written in full.
+    Full,
+}
+
+impl fmt::Display for CopyStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CopyStrategy::Reflink => write!(f, "a reflink"),
+            CopyStrategy::CopyFileRange => write!(f, "copy_file_range"),
+            CopyStrategy::Full => write!(f, "a full copy"),
+        }
+    }
+}
+
+/// Copies the contents and the read, write and execute permissions of `file` to `destination`, replacing it if
+/// it exists, returning how it was copied. With `CopyMode::Auto`, a reflink is tried first, then `copy_file_range`, then a full copy.
+pub fn copy_file(file: &Path, destination: &Path, mode: CopyMode) -> io::Result<CopyStrategy> {
+    let mut source = File::open(file)?;
+    let metadata = source.metadata()?;
+    let mut target = OpenOptions::new().write(true).create(true).truncate(true).open(destination)?;
+    let strategy = match mode {
+        CopyMode::Reflink => {
+            if let Err(error) = reflink(&source, &target) {
+                drop(target);
+                let _ = fs::remove_file(destination);
+                return Err(error);
+            }
+            CopyStrategy::Reflink
+        }
+        CopyMode::Full => {
+            full_copy(&mut source, &mut target)?;
+            CopyStrategy::Full
+        }
+        CopyMode::Auto => {
+            if reflink(&source, &target).is_ok() {
+                CopyStrategy::Reflink
+            } else if copy_file_range(&source, &target, metadata.len()).is_ok() {
+                CopyStrategy::CopyFileRange
+            } else {
+                // copy_file_range may have failed part way through, so start again
+                target.set_len(0)?;
+                full_copy(&mut source, &mut target)?;
+                CopyStrategy::Full
+            }
+        }
+    };
+    fs::set_permissions(destination, without_special_bits(metadata.permissions()))?;
+
+    Ok(strategy)
+}
+
+/// Leaves out the setuid, setgid and sticky bits, which are only copied with `--preserve mode`.
+#[cfg(unix)]
+fn without_special_bits(permissions: fs::Permissions) -> fs::Permissions {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::Permissions::from_mode(permissions.mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn without_special_bits(permissions: fs::Permissions) -> fs::Permissions {
+    permissions
+}
+
+/// Copies `file` to `destination` on another device, to move it there. The file is copied to a temporary file
+/// beside the destination and verified before being renamed into place, so a failure part way through never
+/// leaves a partial file at the destination. Deleting the original is left to the caller, once the run is
+/// committed.
+pub fn copy_for_move(file: &Path, destination: &Path, file_context: &MapFileContext) -> Result<CopyStrategy> {
+    let file_name = destination.file_name()
+        .chain_err(|| format!("Destination {} has no file name. This is a bug.", destination.to_string_lossy()))?;
+    let partial = destination.with_file_name(format!(".{}.map-partial", file_name.to_string_lossy()));
+    copy_into_place(file, &partial, destination, file_context).inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })
+}
+
+fn copy_into_place(file: &Path, partial: &Path, destination: &Path, file_context: &MapFileContext)
+    -> Result<CopyStrategy> {
+    // Reflinks can't cross devices, so don't insist on one
+    let mode = match file_context.copy_mode {
+        CopyMode::Reflink => CopyMode::Auto,
+        mode => mode,
+    };
+    let strategy = copy_file(file, partial, mode)
+        .chain_err(|| format!("Unable to copy file {} to {}", file.to_string_lossy(), partial.to_string_lossy()))?;
+    verify_copy(file, partial, file_context.hash_algorithm)?;
+    preserve_attributes(file, partial, file_context.preserve)?;
+    fs::rename(partial, destination)
+        .chain_err(|| format!("Unable to rename {} to {}", partial.to_string_lossy(), destination.to_string_lossy()))?;
+    sync_directory(destination);
+
+    Ok(strategy)
+}
+
+/// Flushes `destination` to disk and reads it back to check it has the same hash as `file`, deleting it if it
+/// doesn't. Fails with `ErrorKind::VerificationFailed` if they differ.
+pub fn verify_copy(file: &Path, destination: &Path, algorithm: HashAlgorithm) -> Result<()> {
+    File::open(destination)
+        .and_then(|copy| copy.sync_all())
+        .chain_err(|| format!("Unable to flush {} to disk", destination.to_string_lossy()))?;
+    let matches = same_contents(file, destination, algorithm)
+        .chain_err(|| format!("Unable to compare {} with its copy {}", file.to_string_lossy(),
+                              destination.to_string_lossy()))?;
+    if !matches {
+        let _ = fs::remove_file(destination);
+        bail!(ErrorKind::VerificationFailed(file.to_path_buf(), destination.to_path_buf()))
+    }
+    Ok(())
+}
+
+/// Flushes the directory containing `file` to disk, so that a new entry for it survives a crash. This is only
+/// possible on Unix, and failing to do it doesn't stop the file being moved.
+#[cfg(unix)]
+fn sync_directory(file: &Path) {
+    if let Some(directory) = file.parent() {
+        let directory = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+        if let Err(error) = File::open(directory).and_then(|directory| directory.sync_all()) {
+            warn!("Unable to flush directory {} to disk: {}", directory.to_string_lossy(), error);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_file: &Path) {}
+
+/// Reads and writes the whole file, so that no data is shared with the copy.
+fn full_copy(source: &mut File, target: &mut File) -> io::Result<()> {
+    let mut buffer = vec![0; 128 * 1024];
+    loop {
+        let read = source.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        target.write_all(&buffer[..read])?;
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn reflink(source: &File, target: &File) -> io::Result<()> {
+    use libc;
+    use std::os::unix::io::AsRawFd;
+
+    if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn reflink(_source: &File, _target: &File) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Reflinks are only supported on Linux"))
+}
+
+/// Copies `length` bytes with `copy_file_range`, using explicit offsets so neither file's position moves.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn copy_file_range(source: &File, target: &File, length: u64) -> io::Result<()> {
+    use libc;
+    use std::os::unix::io::AsRawFd;
+
+    let mut source_offset: libc::loff_t = 0;
+    let mut target_offset: libc::loff_t = 0;
+    let mut remaining = length;
+    while remaining > 0 {
+        let chunk = remaining.min(1 << 30) as usize;
+        let copied = unsafe {
+            libc::copy_file_range(source.as_raw_fd(), &mut source_offset, target.as_raw_fd(), &mut target_offset,
+                                  chunk, 0)
+        };
+        match copied {
+            -1 => return Err(io::Error::last_os_error()),
+            // The file was shorter than it was when we started
+            0 => return Ok(()),
+            copied => remaining -= copied as u64,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn copy_file_range(_source: &File, _target: &File, _length: u64) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "copy_file_range is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testutils::*;
+
+    #[test]
+    fn copy_file_full_copies_contents_and_permissions() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::write(test_file, "contents").unwrap();
+                let mut permissions = fs::metadata(test_file).unwrap().permissions();
+                permissions.set_readonly(true);
+                fs::set_permissions(test_file, permissions).unwrap();
+                let destination = test_directory.join(random_string() + "_copy");
+                assert_eq!(copy_file(test_file, &destination, CopyMode::Full).unwrap(), CopyStrategy::Full);
+                assert_eq!(fs::read_to_string(&destination).unwrap(), "contents");
+                assert!(fs::metadata(&destination).unwrap().permissions().readonly());
+            })
+        });
+    }
+
+    #[test]
+    fn copy_file_auto_copies_contents() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::write(test_file, "contents").unwrap();
+                let destination = test_directory.join(random_string() + "_copy");
+                fs::write(&destination, "longer previous contents").unwrap();
+                copy_file(test_file, &destination, CopyMode::Auto).unwrap();
+                assert_eq!(fs::read_to_string(&destination).unwrap(), "contents");
+            })
+        });
+    }
+
+    #[test]
+    fn copy_file_reflink_failure_leaves_no_destination() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let destination = test_directory.join(random_string() + "_copy");
+                // Whether reflinks work depends on the filesystem, but a failed one mustn't leave an empty file
+                if copy_file(test_file, &destination, CopyMode::Reflink).is_err() {
+                    assert!(!destination.exists());
+                }
+            })
+        });
+    }
+
+    #[test]
+    fn copy_for_move_copies_file() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::write(test_file, "contents").unwrap();
+                let destination = test_directory.join(random_string() + "_moved");
+                copy_for_move(test_file, &destination, &MapFileContext::default()).unwrap();
+                assert_eq!(fs::read_to_string(&destination).unwrap(), "contents");
+                assert!(test_file.is_file());
+                assert_eq!(fs::read_dir(test_directory).unwrap().count(), 2);
+            })
+        });
+    }
+
+    #[test]
+    fn copy_for_move_failure_leaves_no_partial_file() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let destination = test_directory.join(random_string() + "_missing").join("moved");
+                assert!(copy_for_move(test_file, &destination, &MapFileContext::default()).is_err());
+                assert!(test_file.is_file());
+                assert_eq!(fs::read_dir(test_directory).unwrap().count(), 1);
+            })
+        });
+    }
+
+    #[test]
+    fn verify_copy_deletes_mismatched_copy() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::write(test_file, "contents").unwrap();
+                let destination = test_directory.join(random_string() + "_copy");
+                fs::write(&destination, "contents").unwrap();
+                verify_copy(test_file, &destination, HashAlgorithm::Blake3).unwrap();
+                fs::write(&destination, "cont").unwrap();
+                match verify_copy(test_file, &destination, HashAlgorithm::Blake3) {
+                    Err(Error(ErrorKind::VerificationFailed(..), _)) => (),
+                    result => panic!("Expected verification to fail, but got {:?}", result),
+                }
+                assert!(!destination.exists());
+            })
+        });
+    }
+}
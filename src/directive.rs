@@ -1,11 +1,11 @@
 use regex::{Captures, Regex};
 use std::fmt;
-use std::path::PathBuf;
 
 use action::*;
 use error::*;
+use expression::{parse_rule_expression, rule_expression_start};
 use mapping::Mapping;
-use rule::*;
+use template::Destination;
 
 pub trait MappingDirective: fmt::Display {
     fn create_mapping(&self, definition: &str) -> Option<Result<Mapping>>;
@@ -32,59 +32,72 @@ impl MappingDirective for RegexDirective {
 }
 
 pub fn create_directives() -> Vec<Box<dyn MappingDirective>> {
-    vec![copy_regex_directive(), move_regex_directive()]
+    vec![copy_directive(), move_directive(), symlink_directive(), relative_symlink_directive(), hardlink_directive()]
 }
 
-fn copy_regex_directive() -> Box<dyn MappingDirective> {
-    let directive = RegexDirective {
-        directive_name: "Copy".to_string(),
-        format: Regex::new(r"^\s*c\s*/(?P<regex>.*?)/\s*(?P<destination>.+?)\s*$").unwrap(),
-        action_factory: Box::new(|captures: Captures| {
-            let regex_string = captures
-                .name("regex")
-                .chain_err(|| "No regex found for copy rule. This is a bug.")?;
-            let destination_string = captures
-                .name("destination")
-                .chain_err(|| "No destination found for copy rule. This is a bug.")?;
-            let rule_regex = Regex::new(regex_string.as_str()).chain_err(|| {
-                format!(
-                    "Unable to parse regex for copy rule {}",
-                    regex_string.as_str()
-                )
-            })?;
-            let relative_destination = PathBuf::from(destination_string.as_str());
-            Ok(Mapping::new(
-                Box::new(RegexRule::new(rule_regex)),
-                Box::new(CopyAction::new(relative_destination)),
-            ))
-        }),
-    };
+fn copy_directive() -> Box<dyn MappingDirective> {
+    action_directive("Copy", "c", |relative_destination| {
+        Box::new(CopyAction::new(relative_destination))
+    })
+}
 
-    Box::new(directive)
+fn move_directive() -> Box<dyn MappingDirective> {
+    action_directive("Move", "m", |relative_destination| {
+        Box::new(MoveAction::new(relative_destination))
+    })
+}
+
+fn symlink_directive() -> Box<dyn MappingDirective> {
+    action_directive("Symlink", "s", |relative_destination| {
+        Box::new(SymlinkAction::new(relative_destination, SymlinkKind::Absolute))
+    })
 }
 
-fn move_regex_directive() -> Box<dyn MappingDirective> {
+fn relative_symlink_directive() -> Box<dyn MappingDirective> {
+    action_directive("Relative symlink", "sr", |relative_destination| {
+        Box::new(SymlinkAction::new(relative_destination, SymlinkKind::Relative))
+    })
+}
+
+fn hardlink_directive() -> Box<dyn MappingDirective> {
+    action_directive("Hardlink", "h", |relative_destination| {
+        Box::new(HardlinkAction::new(relative_destination))
+    })
+}
+
+/// A directive of the form `<letter> <rule expression> <relative destination>`.
+fn action_directive(
+    directive_name: &str,
+    letter: &str,
+    action_factory: fn(Destination) -> Box<dyn MapAction>,
+) -> Box<dyn MappingDirective> {
+    let rule_kind = directive_name.to_lowercase();
     let directive = RegexDirective {
-        directive_name: "Move".to_string(),
-        format: Regex::new(r"^\s*m\s*/(?P<regex>.*?)/\s*(?P<destination>.+?)\s*$").unwrap(),
-        action_factory: Box::new(|captures: Captures| {
-            let regex_string = captures
-                .name("regex")
-                .chain_err(|| "No regex found for move rule. This is a bug.")?;
-            let destination_string = captures
-                .name("destination")
-                .chain_err(|| "No destination found for move rule. This is a bug.")?;
-            let rule_regex = Regex::new(regex_string.as_str()).chain_err(|| {
+        directive_name: directive_name.to_string(),
+        format: Regex::new(&format!(
+            r"^\s*{}\s*(?P<definition>(?:{}).*)$",
+            letter,
+            rule_expression_start()
+        ))
+        .unwrap(),
+        action_factory: Box::new(move |captures: Captures| {
+            let definition = captures
+                .name("definition")
+                .chain_err(|| format!("No definition found for {} rule. This is a bug.", rule_kind))?
+                .as_str();
+            let (rule, destination_string) = parse_rule_expression(definition)
+                .chain_err(|| format!("Unable to parse {} rule {}", rule_kind, definition))?;
+            let destination_string = destination_string.trim();
+            if destination_string.is_empty() {
+                bail!("No destination found for {} rule {}", rule_kind, definition);
+            }
+            let relative_destination = Destination::parse(destination_string).chain_err(|| {
                 format!(
-                    "Unable to parse regex for move rule {}",
-                    regex_string.as_str()
+                    "Unable to parse destination for {} rule {}",
+                    rule_kind, destination_string
                 )
             })?;
-            let relative_destination = PathBuf::from(destination_string.as_str());
-            Ok(Mapping::new(
-                Box::new(RegexRule::new(rule_regex)),
-                Box::new(MoveAction::new(relative_destination)),
-            ))
+            Ok(Mapping::new(rule, action_factory(relative_destination)))
         }),
     };
 
@@ -172,16 +185,16 @@ mod test {
     }
 
     #[test]
-    fn copy_regex_directive_create_mapping_no_match() {
-        let copy_regex_directive = copy_regex_directive();
-        assert_eq!(copy_regex_directive.create_mapping("").is_none(), true);
+    fn copy_directive_create_mapping_no_match() {
+        let copy_directive = copy_directive();
+        assert_eq!(copy_directive.create_mapping("").is_none(), true);
     }
 
     #[test]
-    fn copy_regex_directive_create_mapping_invalid_regex() {
-        let copy_regex_directive = copy_regex_directive();
+    fn copy_directive_create_mapping_invalid_regex() {
+        let copy_directive = copy_directive();
         assert_eq!(
-            copy_regex_directive
+            copy_directive
                 .create_mapping("c/(/ destination")
                 .unwrap()
                 .is_err(),
@@ -190,10 +203,10 @@ mod test {
     }
 
     #[test]
-    fn copy_regex_directive_create_mapping_valid() {
-        let copy_regex_directive = copy_regex_directive();
+    fn copy_directive_create_mapping_valid() {
+        let copy_directive = copy_directive();
         assert_eq!(
-            copy_regex_directive
+            copy_directive
                 .create_mapping("c/regex/ destination")
                 .unwrap()
                 .is_ok(),
@@ -202,16 +215,25 @@ mod test {
     }
 
     #[test]
-    fn move_regex_directive_create_mapping_no_match() {
-        let move_regex_directive = move_regex_directive();
-        assert_eq!(move_regex_directive.create_mapping("").is_none(), true);
+    fn copy_directive_create_mapping_invalid_destination() {
+        let copy_directive = copy_directive();
+        assert!(copy_directive
+            .create_mapping("c/regex/ ${1")
+            .unwrap()
+            .is_err());
     }
 
     #[test]
-    fn move_regex_directive_create_mapping_invalid_regex() {
-        let move_regex_directive = move_regex_directive();
+    fn move_directive_create_mapping_no_match() {
+        let move_directive = move_directive();
+        assert_eq!(move_directive.create_mapping("").is_none(), true);
+    }
+
+    #[test]
+    fn move_directive_create_mapping_invalid_regex() {
+        let move_directive = move_directive();
         assert_eq!(
-            move_regex_directive
+            move_directive
                 .create_mapping("m/(/ destination")
                 .unwrap()
                 .is_err(),
@@ -220,10 +242,10 @@ mod test {
     }
 
     #[test]
-    fn move_regex_directive_create_mapping_valid() {
-        let move_regex_directive = move_regex_directive();
+    fn move_directive_create_mapping_valid() {
+        let move_directive = move_directive();
         assert_eq!(
-            move_regex_directive
+            move_directive
                 .create_mapping("m/regex/ destination")
                 .unwrap()
                 .is_ok(),
@@ -231,6 +253,65 @@ mod test {
         );
     }
 
+    #[test]
+    fn copy_directive_create_mapping_rule_expression() {
+        let copy_directive = copy_directive();
+        assert!(copy_directive
+            .create_mapping(r#"c (p/^(\d{4})\/(Q\d)\// | g"*.epub") & !/\.part$/ Reports/${1}-${2}"#)
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn move_directive_create_mapping_predicates() {
+        let move_directive = move_directive();
+        assert!(move_directive
+            .create_mapping(r"m /\.pdf$/ & mtime>90d Archive")
+            .unwrap()
+            .is_ok());
+        assert!(move_directive
+            .create_mapping("m size>10MB Large")
+            .unwrap()
+            .is_ok());
+    }
+
+    #[test]
+    fn copy_directive_create_mapping_no_destination() {
+        let copy_directive = copy_directive();
+        assert!(copy_directive.create_mapping("c /regex/ ").unwrap().is_err());
+    }
+
+    #[test]
+    fn copy_directive_create_mapping_invalid_expression() {
+        let copy_directive = copy_directive();
+        assert!(copy_directive.create_mapping("c /regex/ & destination").unwrap().is_err());
+    }
+
+    #[test]
+    fn move_directive_create_mapping_invalid_glob() {
+        let move_directive = move_directive();
+        assert!(move_directive
+            .create_mapping("m g\"[*.epub\" Books/EPUB")
+            .unwrap()
+            .is_err());
+    }
+
+    #[test]
+    fn copy_directive_ignores_other_lines() {
+        let copy_directive = copy_directive();
+        assert!(copy_directive.create_mapping("comment").is_none());
+    }
+
+    #[test]
+    fn create_directives_are_not_ambiguous() {
+        let directives = create_directives();
+        for definition in &["c/regex/ destination", "m p/regex/ destination", "c g\"*.epub\" destination",
+                            "s/regex/ destination", "sr/regex/ destination", "sr readonly destination",
+                            "h size>1MB destination"] {
+            assert!(mapping_from_string(&directives, definition).unwrap().is_ok());
+        }
+    }
+
     #[test]
     fn create_mapping_regex_directive_no_matches() {
         assert_eq!(
@@ -0,0 +1,356 @@
+use regex::bytes::Regex as BytesRegex;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+use content_type::ContentType;
+use error::*;
+
+/// Names of the fields that are read from ebooks, which are also the names of their template variables.
+pub const EBOOK_FIELD_NAMES: &[&str] = &["title", "author", "publisher", "series"];
+
+/// The metadata of an ebook, read from the OPF package document of an EPUB or the Info dictionary of a PDF.
+pub struct EbookMetadata {
+    fields: HashMap<&'static str, String>,
+}
+
+impl EbookMetadata {
+    /// Reads the metadata of `file`, or returns `None` if it isn't an EPUB or PDF with metadata.
+    pub fn read(file: &Path) -> Result<Option<EbookMetadata>> {
+        let mut metadata = EbookMetadata { fields: HashMap::new() };
+        match ContentType::detect(file)? {
+            Some(ContentType::Epub) => metadata
+                .read_epub(file)
+                .chain_err(|| format!("Unable to read EPUB metadata of {}", file.to_string_lossy()))?,
+            Some(ContentType::Pdf) => metadata
+                .read_pdf(file)
+                .chain_err(|| format!("Unable to read PDF metadata of {}", file.to_string_lossy()))?,
+            _ => return Ok(None),
+        }
+
+        if metadata.fields.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(metadata))
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    fn set(&mut self, name: &'static str, value: &str) {
+        let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if !value.is_empty() {
+            self.fields.entry(name).or_insert(value);
+        }
+    }
+
+    fn read_epub(&mut self, file: &Path) -> Result<()> {
+        let opened = File::open(file).chain_err(|| format!("Unable to open {}", file.to_string_lossy()))?;
+        let mut archive = ZipArchive::new(opened).chain_err(|| "Unable to read zip archive")?;
+        let container = read_zip_entry(&mut archive, "META-INF/container.xml")?;
+        let package_path = match first_capture(r#"full-path\s*=\s*["']([^"']+)["']"#, &container) {
+            Some(package_path) => package_path,
+            None => bail!("No package document in META-INF/container.xml"),
+        };
+        let package = read_zip_entry(&mut archive, &decode_xml_text(&package_path))?;
+
+        for &(name, element) in &[("title", "title"), ("author", "creator"), ("publisher", "publisher")] {
+            let pattern = format!(r"(?s)<(?:dc:)?{0}\b[^>]*>(.*?)</(?:dc:)?{0}>", element);
+            if let Some(value) = first_capture(&pattern, &package) {
+                self.set(name, &decode_xml_text(&value));
+            }
+        }
+        // Calibre stores series in a <meta name="calibre:series" content="..."/>, EPUB 3 in a collection
+        let meta_regex = Regex::new(r"(?s)<meta\b([^>]*?)/?>(?:([^<]*)</meta>)?").unwrap();
+        for meta in meta_regex.captures_iter(&package) {
+            let attributes = xml_attributes(&meta[1]);
+            let series = match attributes.get("name").map(String::as_str) {
+                Some("calibre:series") => attributes.get("content").cloned(),
+                _ if attributes.get("property").map(String::as_str) == Some("belongs-to-collection") => {
+                    meta.get(2).map(|text| text.as_str().to_string())
+                }
+                _ => None,
+            };
+            if let Some(series) = series {
+                self.set("series", &decode_xml_text(&series));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the Info dictionary that the trailer of a PDF refers to. Info dictionaries inside of
+    /// compressed object streams aren't read.
+    fn read_pdf(&mut self, file: &Path) -> Result<()> {
+        let pdf = fs::read(file).chain_err(|| format!("Unable to read {}", file.to_string_lossy()))?;
+        let info_reference = BytesRegex::new(r"/Info\s*(\d+)\s+(\d+)\s+R").unwrap();
+        let info = match info_reference.captures_iter(&pdf).last() {
+            Some(reference) => pdf_object(&pdf, &reference[1], &reference[2]),
+            None => None,
+        };
+        let info = match info {
+            Some(info) => info,
+            None => return Ok(()),
+        };
+
+        let key_regex = BytesRegex::new(r"/(Title|Author|Publisher|Series)[\s(<]").unwrap();
+        let indirect_reference = BytesRegex::new(r"^\s*(\d+)\s+(\d+)\s+R").unwrap();
+        for key in key_regex.captures_iter(info) {
+            let name = match &key[1] {
+                b"Title" => "title",
+                b"Author" => "author",
+                b"Publisher" => "publisher",
+                _ => "series",
+            };
+            let value_start = &info[key.get(0).unwrap().end() - 1..];
+            let value = match indirect_reference.captures(value_start) {
+                Some(reference) => pdf_object(&pdf, &reference[1], &reference[2]).and_then(parse_pdf_string),
+                None => parse_pdf_string(value_start),
+            };
+            if let Some(value) = value {
+                self.set(name, &value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_zip_entry(archive: &mut ZipArchive<File>, name: &str) -> Result<String> {
+    let mut entry = archive.by_name(name).chain_err(|| format!("No {} in archive", name))?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content).chain_err(|| format!("Unable to read {} in archive", name))?;
+
+    Ok(content)
+}
+
+fn first_capture(pattern: &str, text: &str) -> Option<String> {
+    Regex::new(pattern)
+        .unwrap()
+        .captures(text)
+        .and_then(|captures| captures.get(1))
+        .map(|capture| capture.as_str().to_string())
+}
+
+fn xml_attributes(tag: &str) -> HashMap<String, String> {
+    let attribute_regex = Regex::new(r#"([\w:.-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    attribute_regex
+        .captures_iter(tag)
+        .map(|attribute| {
+            let value = attribute.get(2).or_else(|| attribute.get(3)).map_or("", |value| value.as_str());
+            (attribute[1].to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Replaces XML entities and character references with the text they stand for.
+fn decode_xml_text(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut remaining = text;
+    while let Some(start) = remaining.find('&') {
+        decoded.push_str(&remaining[..start]);
+        remaining = &remaining[start..];
+        let end = match remaining.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let entity = &remaining[1..end];
+        let character = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16).ok().and_then(::std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(::std::char::from_u32),
+            _ => None,
+        };
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                remaining = &remaining[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                remaining = &remaining[1..];
+            }
+        }
+    }
+    decoded.push_str(remaining);
+
+    decoded
+}
+
+/// The content of the last definition of object `number generation obj`, up to its `endobj`.
+fn pdf_object<'a>(pdf: &'a [u8], number: &[u8], generation: &[u8]) -> Option<&'a [u8]> {
+    let pattern = format!(
+        r"(?:^|[^0-9]){}\s+{}\s+obj",
+        String::from_utf8_lossy(number),
+        String::from_utf8_lossy(generation)
+    );
+    let object_start = BytesRegex::new(&pattern).unwrap().find_iter(pdf).last()?.end();
+    let object = &pdf[object_start..];
+    let length = object.windows(6).position(|window| window == b"endobj").unwrap_or(object.len());
+
+    Some(&object[..length])
+}
+
+/// Parses the PDF literal `(...)` or hex `<...>` string at the start of `data`, ignoring leading whitespace.
+fn parse_pdf_string(data: &[u8]) -> Option<String> {
+    let start = data.iter().position(|byte| !byte.is_ascii_whitespace())?;
+    let data = &data[start..];
+    let bytes = match data.first()? {
+        b'(' => parse_pdf_literal_string(&data[1..]),
+        b'<' if data.get(1) != Some(&b'<') => {
+            let end = data.iter().position(|&byte| byte == b'>')?;
+            let mut digits: Vec<u8> = data[1..end].iter().cloned().filter(u8::is_ascii_hexdigit).collect();
+            if digits.len() % 2 == 1 {
+                digits.push(b'0');
+            }
+            digits
+                .chunks(2)
+                .map(|pair| u8::from_str_radix(&String::from_utf8_lossy(pair), 16).unwrap_or_default())
+                .collect()
+        }
+        _ => return None,
+    };
+
+    Some(decode_pdf_text(&bytes))
+}
+
+fn parse_pdf_literal_string(data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut depth = 1;
+    let mut index = 0;
+    while index < data.len() {
+        let byte = data[index];
+        index += 1;
+        match byte {
+            b'\\' => {
+                let escaped = match data.get(index) {
+                    Some(&escaped) => escaped,
+                    None => break,
+                };
+                index += 1;
+                match escaped {
+                    b'n' => bytes.push(b'\n'),
+                    b'r' => bytes.push(b'\r'),
+                    b't' => bytes.push(b'\t'),
+                    b'b' => bytes.push(0x08),
+                    b'f' => bytes.push(0x0C),
+                    b'0'..=b'7' => {
+                        let mut value = u32::from(escaped - b'0');
+                        for _ in 0..2 {
+                            match data.get(index) {
+                                Some(&digit @ b'0'..=b'7') => {
+                                    value = value * 8 + u32::from(digit - b'0');
+                                    index += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        bytes.push(value as u8);
+                    }
+                    // A backslash at the end of a line continues the string on the next line
+                    b'\r' => {
+                        if data.get(index) == Some(&b'\n') {
+                            index += 1;
+                        }
+                    }
+                    b'\n' => (),
+                    other => bytes.push(other),
+                }
+            }
+            b'(' => {
+                depth += 1;
+                bytes.push(byte);
+            }
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+                bytes.push(byte);
+            }
+            _ => bytes.push(byte),
+        }
+    }
+
+    bytes
+}
+
+/// PDF text strings are UTF-16 if they start with a byte order mark, and otherwise (approximately) Latin-1.
+fn decode_pdf_text(bytes: &[u8]) -> String {
+    if bytes.starts_with(b"\xFE\xFF") {
+        let units: Vec<u16> = bytes[2..]
+            .chunks(2)
+            .filter(|unit| unit.len() == 2)
+            .map(|unit| u16::from(unit[0]) << 8 | u16::from(unit[1]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else if bytes.starts_with(b"\xEF\xBB\xBF") {
+        String::from_utf8_lossy(&bytes[3..]).into_owned()
+    } else {
+        bytes.iter().map(|&byte| char::from(byte)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use testutils::*;
+
+    fn read_metadata(content: &[u8]) -> Option<EbookMetadata> {
+        let metadata = RefCell::new(None);
+        with_default_test_directory(|test_directory| {
+            let file = test_directory.join("book");
+            File::create(&file).unwrap().write_all(content).unwrap();
+            *metadata.borrow_mut() = EbookMetadata::read(&file).unwrap();
+        });
+        metadata.into_inner()
+    }
+
+    #[test]
+    fn ebook_metadata_from_epub() {
+        let metadata = read_metadata(&epub_with_metadata()).unwrap();
+        assert_eq!(metadata.get("title"), Some("Evil by Design"));
+        assert_eq!(metadata.get("author"), Some("Chris Nodder"));
+        assert_eq!(metadata.get("publisher"), Some("Wiley & Sons"));
+        assert_eq!(metadata.get("series"), Some("Design Classics"));
+    }
+
+    #[test]
+    fn ebook_metadata_from_pdf() {
+        let metadata = read_metadata(&pdf_with_info()).unwrap();
+        assert_eq!(metadata.get("title"), Some("Evil by Design (2nd Edition)"));
+        assert_eq!(metadata.get("author"), Some("Chris Nodder"));
+        assert_eq!(metadata.get("publisher"), None);
+    }
+
+    #[test]
+    fn ebook_metadata_from_pdf_indirect_and_utf16_strings() {
+        let pdf = b"%PDF-1.4\n1 0 obj\n<< /Title 2 0 R /Author <FEFF004A00F6> >>\nendobj\n\
+                    2 0 obj\n(Design \\(Ed. 1\\)\\\nDraft\\041)\nendobj\ntrailer\n<< /Info 1 0 R >>\n%%EOF\n";
+        let metadata = read_metadata(pdf).unwrap();
+        assert_eq!(metadata.get("title"), Some("Design (Ed. 1)Draft!"));
+        assert_eq!(metadata.get("author"), Some("Jö"));
+    }
+
+    #[test]
+    fn ebook_metadata_of_other_files() {
+        assert!(read_metadata(b"%PDF-1.4\ntrailer\n<< >>\n").is_none());
+        assert!(read_metadata(&mp3_with_id3()).is_none());
+    }
+
+    #[test]
+    fn decode_xml_text_entities() {
+        assert_eq!(decode_xml_text("A &amp; B &#233;&#x2014;&unknown; &"), "A & B é—&unknown; &");
+    }
+}
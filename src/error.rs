@@ -1 +1,11 @@
-error_chain! { }
\ No newline at end of file
+use std::path::PathBuf;
+
+error_chain! {
+    errors {
+        /// A copy didn't have the same contents as its original when read back.
+        VerificationFailed(file: PathBuf, destination: PathBuf) {
+            description("copy doesn't match the original")
+            display("Copy {} doesn't match the original {}", destination.to_string_lossy(), file.to_string_lossy())
+        }
+    }
+}
@@ -0,0 +1,374 @@
+use regex::Regex;
+
+use audio::AudioRule;
+use content_type::ContentTypeRule;
+use error::*;
+use glob::Glob;
+use metadata::*;
+use photo::ExifRule;
+use rule::*;
+use tags::{tag_names, TagRule};
+
+/// Names of the rules written as `<name>[<operator><value>]`, e.g. `size>10MB`, besides those of tags.
+const PREDICATE_NAMES: &[&str] = &["size", "mtime", "ctime", "btime", "atime", "mode", "owner", "readonly", "type", "taken", "exif", "ext", "audio"];
+
+/// A regex matching the start of a rule expression, so that directives can tell whether a definition
+/// is meant for them before parsing it.
+pub fn rule_expression_start() -> String {
+    format!(r#"[/!(]|p/|g"|(?:{}|{})\b"#, PREDICATE_NAMES.join("|"), tag_names().join("|"))
+}
+
+/// Parses a rule expression from the start of `definition`, returning the rule and the remaining text.
+///
+/// Rules are `/<regex>/`, `p/<regex>/`, `g"<glob>"` and predicates such as `size>10MB`, and may be
+/// combined with `!` (not), `&` (and), `|` (or) and parentheses. `&` binds more tightly than `|`.
+pub fn parse_rule_expression(definition: &str) -> Result<(Box<dyn MapRule>, &str)> {
+    let mut parser = Parser {
+        text: definition,
+        position: 0,
+    };
+    let rule = parser.parse_any()?;
+
+    Ok((rule, &definition[parser.position..]))
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse_any(&mut self) -> Result<Box<dyn MapRule>> {
+        let mut rules = vec![self.parse_all()?];
+        while self.consume('|') {
+            rules.push(self.parse_all()?);
+        }
+
+        Ok(combine(rules, |rules| Box::new(AnyRule::new(rules))))
+    }
+
+    fn parse_all(&mut self) -> Result<Box<dyn MapRule>> {
+        let mut rules = vec![self.parse_unary()?];
+        while self.consume('&') {
+            rules.push(self.parse_unary()?);
+        }
+
+        Ok(combine(rules, |rules| Box::new(AllRule::new(rules))))
+    }
+
+    fn parse_unary(&mut self) -> Result<Box<dyn MapRule>> {
+        if self.consume('!') {
+            Ok(Box::new(NotRule::new(self.parse_unary()?)))
+        } else if self.consume('(') {
+            let rule = self.parse_any()?;
+            if !self.consume(')') {
+                bail!("Expected ')' at '{}'", self.remaining());
+            }
+            Ok(rule)
+        } else {
+            self.parse_rule()
+        }
+    }
+
+    fn parse_rule(&mut self) -> Result<Box<dyn MapRule>> {
+        self.skip_whitespace();
+        if self.remaining().starts_with("p/") {
+            self.position += 2;
+            let regex = self.parse_regex()?;
+            Ok(Box::new(RelativePathRegexRule::new(regex)))
+        } else if self.remaining().starts_with('/') {
+            self.position += 1;
+            let regex = self.parse_regex()?;
+            Ok(Box::new(RegexRule::new(regex)))
+        } else if self.remaining().starts_with("g\"") {
+            self.position += 2;
+            let glob_string = self.take_until('"', "glob")?;
+            let glob = Glob::new(glob_string).chain_err(|| format!("Unable to parse glob {}", glob_string))?;
+            Ok(Box::new(GlobRule::new(glob)))
+        } else if let Some(name) = self.take_predicate_name() {
+            self.parse_predicate(name)
+        } else {
+            bail!("Expected a rule at '{}'", self.remaining())
+        }
+    }
+
+    /// Parses the optional operator and value following a predicate's name. Values end at whitespace or `)`,
+    /// unless they are quoted, as in `author:"Neil Gaiman"`.
+    fn parse_predicate(&mut self, name: &str) -> Result<Box<dyn MapRule>> {
+        let operator = match Operator::parse(self.remaining()) {
+            Some((operator, length)) => {
+                self.position += length;
+                Some(operator)
+            }
+            None => None,
+        };
+        let remaining = self.remaining();
+        let value = if remaining.starts_with('"') {
+            self.position += 1;
+            self.take_until('"', "quoted value")?
+        } else {
+            let value_length = remaining
+                .find(|character: char| character.is_whitespace() || character == ')')
+                .unwrap_or(remaining.len());
+            self.position += value_length;
+            &remaining[..value_length]
+        };
+        if let (Some(operator), true) = (operator, value.is_empty()) {
+            bail!("Expected a value after {}{}", name, operator);
+        }
+
+        predicate_rule(name, operator, value).chain_err(|| format!("Unable to parse {} rule", name))
+    }
+
+    fn take_predicate_name(&mut self) -> Option<&'a str> {
+        let remaining = self.remaining();
+        let name_length = remaining
+            .find(|character: char| !(character.is_ascii_lowercase() || character == '_'))
+            .unwrap_or(remaining.len());
+        let name = &remaining[..name_length];
+        if PREDICATE_NAMES.contains(&name) || tag_names().contains(&name) {
+            self.position += name_length;
+            Some(name)
+        } else {
+            None
+        }
+    }
+
+    /// Parses a regex up to its closing, unescaped `/`.
+    fn parse_regex(&mut self) -> Result<Regex> {
+        let start = self.position;
+        let mut characters = self.remaining().char_indices();
+        loop {
+            match characters.next() {
+                Some((_, '\\')) => {
+                    characters.next();
+                }
+                Some((offset, '/')) => {
+                    let regex_string = &self.text[start..start + offset];
+                    self.position = start + offset + 1;
+                    return Regex::new(&unescape_slashes(regex_string))
+                        .chain_err(|| format!("Unable to parse regex {}", regex_string));
+                }
+                Some(_) => (),
+                None => bail!("Unterminated regex at '{}'", &self.text[start..]),
+            }
+        }
+    }
+
+    fn take_until(&mut self, delimiter: char, description: &str) -> Result<&'a str> {
+        let text = self.text;
+        let start = self.position;
+        match text[start..].find(delimiter) {
+            Some(offset) => {
+                self.position = start + offset + delimiter.len_utf8();
+                Ok(&text[start..start + offset])
+            }
+            None => bail!("Unterminated {} at '{}'", description, &text[start..]),
+        }
+    }
+
+    fn consume(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        if self.remaining().starts_with(expected) {
+            self.position += expected.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let remaining = self.remaining();
+        self.position += remaining.len() - remaining.trim_start().len();
+    }
+
+    fn remaining(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+}
+
+fn predicate_rule(name: &str, operator: Option<Operator>, value: &str) -> Result<Box<dyn MapRule>> {
+    let rule: Box<dyn MapRule> = match name {
+        "size" => Box::new(SizeRule::parse(operator, value)?),
+        "mtime" | "ctime" | "btime" | "atime" | "taken" => {
+            let kind = TimeKind::from_name(name).chain_err(|| format!("Unknown time {}. This is a bug.", name))?;
+            Box::new(TimeRule::parse(kind, operator, value)?)
+        }
+        "mode" => Box::new(ModeRule::parse(operator, value)?),
+        "owner" => Box::new(OwnerRule::parse(operator, value)?),
+        "readonly" => {
+            if let Some(operator) = operator {
+                bail!("Unexpected {}{} after readonly", operator, value);
+            }
+            Box::new(ReadOnlyRule)
+        }
+        "type" => Box::new(ContentTypeRule::parse(operator, value)?),
+        "exif" => Box::new(ExifRule::parse(operator, value)?),
+        "ext" => Box::new(ExtensionRule::parse(operator, value)?),
+        "audio" => Box::new(AudioRule::parse(operator, value)?),
+        _ if tag_names().contains(&name) => Box::new(TagRule::parse(name, operator, value)?),
+        _ => bail!("Unknown rule {}. This is a bug.", name),
+    };
+
+    Ok(rule)
+}
+
+fn combine(
+    mut rules: Vec<Box<dyn MapRule>>,
+    combinator: impl Fn(Vec<Box<dyn MapRule>>) -> Box<dyn MapRule>,
+) -> Box<dyn MapRule> {
+    if rules.len() == 1 {
+        rules.pop().unwrap()
+    } else {
+        combinator(rules)
+    }
+}
+
+/// Regexes are delimited by `/`, so literal slashes within them are written as `\/`.
+fn unescape_slashes(regex: &str) -> String {
+    let mut unescaped = String::with_capacity(regex.len());
+    let mut characters = regex.chars();
+    while let Some(character) = characters.next() {
+        if character == '\\' {
+            match characters.next() {
+                Some('/') => unescaped.push('/'),
+                Some(escaped) => {
+                    unescaped.push('\\');
+                    unescaped.push(escaped);
+                }
+                None => unescaped.push('\\'),
+            }
+        } else {
+            unescaped.push(character);
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_rule_expression_single_regex() {
+        let (rule, remaining) = parse_rule_expression("/design/ Design").unwrap();
+        assert_eq!(format!("{:?}", rule), "/design/");
+        assert_eq!(remaining, "Design");
+    }
+
+    #[test]
+    fn parse_rule_expression_rule_kinds() {
+        let (rule, remaining) = parse_rule_expression(r#"p/^a\/b/ | g"*.epub"Books"#).unwrap();
+        assert_eq!(format!("{:?}", rule), r#"(p/^a\/b/ | g"*.epub")"#);
+        assert_eq!(remaining, "Books");
+    }
+
+    #[test]
+    fn parse_rule_expression_precedence() {
+        let (rule, _) = parse_rule_expression("/a/ | /b/ & !/c/ dest").unwrap();
+        assert_eq!(format!("{:?}", rule), "(/a/ | (/b/ & !/c/))");
+    }
+
+    #[test]
+    fn parse_rule_expression_nesting() {
+        let (rule, remaining) = parse_rule_expression("!(/a/ | /b/) & (/c/) dest").unwrap();
+        assert_eq!(format!("{:?}", rule), "(!(/a/ | /b/) & /c/)");
+        assert_eq!(remaining, "dest");
+    }
+
+    #[test]
+    fn parse_rule_expression_unclosed_parenthesis() {
+        assert!(parse_rule_expression("(/a/ | /b/ dest").is_err());
+    }
+
+    #[test]
+    fn parse_rule_expression_missing_rule() {
+        assert!(parse_rule_expression("/a/ & dest").is_err());
+    }
+
+    #[test]
+    fn parse_rule_expression_unterminated_regex() {
+        assert!(parse_rule_expression(r"/a\/ dest").is_err());
+    }
+
+    #[test]
+    fn parse_rule_expression_invalid_regex() {
+        assert!(parse_rule_expression("/(/ dest").is_err());
+    }
+
+    #[test]
+    fn parse_rule_expression_invalid_glob() {
+        assert!(parse_rule_expression("g\"[a\" dest").is_err());
+    }
+
+    #[test]
+    fn parse_rule_expression_predicates() {
+        let (rule, remaining) = parse_rule_expression(r"/\.pdf$/ & (mtime>90d | size>=1.5MB) & !readonly Archive").unwrap();
+        assert_eq!(format!("{:?}", rule), r"(/\.pdf$/ & (mtime>90d | size>=1.5MB) & !readonly)");
+        assert_eq!(remaining, "Archive");
+    }
+
+    #[test]
+    fn parse_rule_expression_content_type() {
+        let (rule, remaining) = parse_rule_expression("type:epub|mobi | type:pdf Books").unwrap();
+        assert_eq!(format!("{:?}", rule), "(type:epub|mobi | type:pdf)");
+        assert_eq!(remaining, "Books");
+    }
+
+    #[test]
+    fn parse_rule_expression_photo_predicates() {
+        let (rule, remaining) = parse_rule_expression("exif:gps & taken>2023-01-01 | !exif Photos").unwrap();
+        assert_eq!(format!("{:?}", rule), "((exif:gps & taken>2023-01-01) | !exif)");
+        assert_eq!(remaining, "Photos");
+    }
+
+    #[test]
+    fn parse_rule_expression_audio_predicates() {
+        let (rule, remaining) = parse_rule_expression("ext:MP3|.flac & audio:album Music/{artist}").unwrap();
+        assert_eq!(format!("{:?}", rule), "(ext:mp3|flac & audio:album)");
+        assert_eq!(remaining, "Music/{artist}");
+    }
+
+    #[test]
+    fn parse_rule_expression_tag_predicates() {
+        let (rule, remaining) = parse_rule_expression(r#"type:epub & (author:"Neil Gaiman" | series:discworld) Books"#).unwrap();
+        assert_eq!(format!("{:?}", rule), r#"(type:epub & (author:"Neil Gaiman" | series:discworld))"#);
+        assert_eq!(remaining, "Books");
+    }
+
+    #[test]
+    fn parse_rule_expression_unterminated_quoted_value() {
+        assert!(parse_rule_expression(r#"author:"Neil Gaiman Books"#).is_err());
+    }
+
+    #[test]
+    fn parse_rule_expression_predicate_missing_value() {
+        assert!(parse_rule_expression("size> dest").is_err());
+    }
+
+    #[test]
+    fn parse_rule_expression_invalid_predicate_value() {
+        assert!(parse_rule_expression("mtime<soon dest").is_err());
+        assert!(parse_rule_expression("readonly:yes dest").is_err());
+    }
+
+    #[test]
+    fn parse_rule_expression_unknown_predicate() {
+        assert!(parse_rule_expression("colour:red dest").is_err());
+    }
+
+    #[test]
+    fn rule_expression_start_matches_predicates() {
+        let start = Regex::new(&format!("^(?:{})", rule_expression_start())).unwrap();
+        assert!(start.is_match("mtime>90d"));
+        assert!(start.is_match("readonly Archive"));
+        assert!(start.is_match("g\"*.pdf\""));
+        assert!(!start.is_match("sizeable"));
+    }
+
+    #[test]
+    fn unescape_slashes_keeps_other_escapes() {
+        assert_eq!(unescape_slashes(r"^(\d{4})\/Q\\"), r"^(\d{4})/Q\\");
+    }
+}
@@ -0,0 +1,174 @@
+use regex::Regex;
+use std::fmt;
+
+use error::*;
+
+/// A shell-style wildcard pattern, matched against the whole of a file name or relative path.
+///
+/// Supports `*` and `?` (which don't match `/`), `**` (which does), `[...]`/`[!...]` character
+/// classes, `{a,b}` alternatives and `\` escapes.
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Glob> {
+        let regex_string = glob_to_regex(pattern)?;
+        let regex = Regex::new(&regex_string)
+            .chain_err(|| format!("Unable to parse glob {}", pattern))?;
+        Ok(Glob {
+            pattern: pattern.to_string(),
+            regex,
+        })
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+
+    /// Whether the pattern refers to whole relative paths rather than to single names.
+    pub fn has_path_separator(&self) -> bool {
+        self.pattern.contains('/')
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+impl fmt::Debug for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Glob({:?})", self.pattern)
+    }
+}
+
+fn glob_to_regex(pattern: &str) -> Result<String> {
+    let characters: Vec<char> = pattern.chars().collect();
+    let mut regex = String::from("^");
+    let mut alternative_depth = 0;
+    let mut index = 0;
+    while index < characters.len() {
+        match characters[index] {
+            '*' if characters.get(index + 1) == Some(&'*') => {
+                index += 1;
+                if characters.get(index + 1) == Some(&'/') {
+                    index += 1;
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => index = push_character_class(pattern, &characters, index, &mut regex)?,
+            '{' => {
+                alternative_depth += 1;
+                regex.push_str("(?:");
+            }
+            ',' if alternative_depth > 0 => regex.push('|'),
+            '}' if alternative_depth > 0 => {
+                alternative_depth -= 1;
+                regex.push(')');
+            }
+            '\\' => {
+                index += 1;
+                match characters.get(index) {
+                    Some(escaped) => regex.push_str(&::regex::escape(&escaped.to_string())),
+                    None => bail!("Glob {} ends with an unfinished escape", pattern),
+                }
+            }
+            literal => regex.push_str(&::regex::escape(&literal.to_string())),
+        }
+        index += 1;
+    }
+    if alternative_depth > 0 {
+        bail!("Glob {} has an unclosed '{{'", pattern);
+    }
+    regex.push('$');
+
+    Ok(regex)
+}
+
+/// Translates the character class starting at `start` and returns the index of its closing `]`.
+fn push_character_class(pattern: &str, characters: &[char], start: usize, regex: &mut String) -> Result<usize> {
+    let mut index = start + 1;
+    regex.push('[');
+    if let Some('!') | Some('^') = characters.get(index) {
+        regex.push('^');
+        index += 1;
+    }
+    let first = index;
+    loop {
+        match characters.get(index) {
+            None => bail!("Glob {} has an unclosed '['", pattern),
+            Some(']') if index > first => break,
+            Some('-') if index > first && characters.get(index + 1) != Some(&']') => regex.push('-'),
+            Some(&character) => {
+                if "\\[]^&~-".contains(character) {
+                    regex.push('\\');
+                }
+                regex.push(character);
+            }
+        }
+        index += 1;
+    }
+    regex.push(']');
+
+    Ok(index)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn glob_star_does_not_cross_directories() {
+        let glob = Glob::new("*.epub").unwrap();
+        assert!(glob.is_match("book.epub"));
+        assert!(!glob.is_match("book.pdf"));
+        assert!(!glob.is_match("nested/book.epub"));
+    }
+
+    #[test]
+    fn glob_double_star_crosses_directories() {
+        let glob = Glob::new("**/*.epub").unwrap();
+        assert!(glob.is_match("book.epub"));
+        assert!(glob.is_match("nested/deeper/book.epub"));
+        assert!(!glob.is_match("nested/book.pdf"));
+    }
+
+    #[test]
+    fn glob_question_mark_and_classes() {
+        let glob = Glob::new("report-?[0-9][!a-z].pdf").unwrap();
+        assert!(glob.is_match("report-Q3X.pdf"));
+        assert!(!glob.is_match("report-Q3x.pdf"));
+        assert!(!glob.is_match("report-/3X.pdf"));
+    }
+
+    #[test]
+    fn glob_alternatives() {
+        let glob = Glob::new("*.{epub,mobi,pdf}").unwrap();
+        assert!(glob.is_match("book.mobi"));
+        assert!(!glob.is_match("book.zip"));
+    }
+
+    #[test]
+    fn glob_escapes_regex_characters() {
+        let glob = Glob::new("a+b (1)\\*.txt").unwrap();
+        assert!(glob.is_match("a+b (1)*.txt"));
+        assert!(!glob.is_match("a+b (1)x.txt"));
+    }
+
+    #[test]
+    fn glob_unclosed_class() {
+        assert!(Glob::new("[abc").is_err());
+    }
+
+    #[test]
+    fn glob_unclosed_alternative() {
+        assert!(Glob::new("{a,b").is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use blake3;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use context::HashAlgorithm;
+
+/// The hash of the contents of `file`, in hexadecimal.
+pub fn hash_file(file: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+    let mut opened = File::open(file)?;
+    let mut buffer = vec![0; 64 * 1024];
+    let digest = match algorithm {
+        HashAlgorithm::Blake3 => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(&mut opened, &mut buffer, |chunk| {
+                hasher.update(chunk);
+            })?;
+            hasher.finalize().as_bytes().to_vec()
+        }
+        HashAlgorithm::Sha256 => {
+            let mut hasher = Sha256::new();
+            read_chunks(&mut opened, &mut buffer, |chunk| hasher.update(chunk))?;
+            hasher.finalize().to_vec()
+        }
+    };
+
+    Ok(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Whether two files have the same contents, judged by their sizes and then their hashes.
+pub fn same_contents(first: &Path, second: &Path, algorithm: HashAlgorithm) -> io::Result<bool> {
+    if fs::metadata(first)?.len() != fs::metadata(second)?.len() {
+        return Ok(false);
+    }
+    Ok(hash_file(first, algorithm)? == hash_file(second, algorithm)?)
+}
+
+/// The hash of the contents of `file`, prefixed with the algorithm as in `blake3:...`, so that it can be checked
+/// later without knowing which algorithm made it.
+pub fn tagged_hash(file: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+    let name = match algorithm {
+        HashAlgorithm::Blake3 => "blake3",
+        HashAlgorithm::Sha256 => "sha256",
+    };
+    Ok(format!("{}:{}", name, hash_file(file, algorithm)?))
+}
+
+/// Whether `file` still has the contents that `tagged_hash` gave `tagged` for.
+pub fn has_tagged_hash(file: &Path, tagged: &str) -> io::Result<bool> {
+    let algorithm = match tagged.split(':').next() {
+        Some("blake3") => HashAlgorithm::Blake3,
+        Some("sha256") => HashAlgorithm::Sha256,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown hash {}", tagged))),
+    };
+    Ok(tagged_hash(file, algorithm)? == tagged)
+}
+
+fn read_chunks(file: &mut File, buffer: &mut [u8], mut consume: impl FnMut(&[u8])) -> io::Result<()> {
+    loop {
+        let read = file.read(buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        consume(&buffer[..read]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testutils::*;
+
+    #[test]
+    fn hash_file_with_each_algorithm() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::write(test_file, "abc").unwrap();
+                assert_eq!(hash_file(test_file, HashAlgorithm::Sha256).unwrap(),
+                           "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+                assert_eq!(hash_file(test_file, HashAlgorithm::Blake3).unwrap(),
+                           "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+            })
+        });
+    }
+
+    #[test]
+    fn has_tagged_hash_uses_tagged_algorithm() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::write(test_file, "abc").unwrap();
+                let tagged = tagged_hash(test_file, HashAlgorithm::Sha256).unwrap();
+                assert!(tagged.starts_with("sha256:ba7816bf"));
+                assert!(has_tagged_hash(test_file, &tagged).unwrap());
+                fs::write(test_file, "abd").unwrap();
+                assert!(!has_tagged_hash(test_file, &tagged).unwrap());
+                assert!(has_tagged_hash(test_file, "md5:0").is_err());
+            })
+        });
+    }
+
+    #[test]
+    fn same_contents_compares_hashes() {
+        with_default_test_directory(|test_directory| {
+            let first = test_directory.join(random_string());
+            let second = test_directory.join(random_string());
+            fs::write(&first, "contents").unwrap();
+            fs::write(&second, "contents").unwrap();
+            assert!(same_contents(&first, &second, HashAlgorithm::Blake3).unwrap());
+            fs::write(&second, "contentz").unwrap();
+            assert!(!same_contents(&first, &second, HashAlgorithm::Sha256).unwrap());
+            fs::write(&second, "content").unwrap();
+            assert!(!same_contents(&first, &second, HashAlgorithm::Blake3).unwrap());
+        });
+    }
+}
@@ -0,0 +1,498 @@
+use chrono::Local;
+use serde_json;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use context::{CopyMode, HashAlgorithm, MapFileContext, Preserve};
+use copy::{copy_file, copy_for_move};
+use error::*;
+use hash::{has_tagged_hash, tagged_hash};
+use journal::{Entry, Operation};
+
+/// What a run did, as written to its journal file.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RecordedOperation {
+    CreateDirectory,
+    Copy,
+    Link,
+    Move,
+    /// The file at the destination was replaced. A copy of it is kept at the record's `backup`.
+    Overwrite,
+    /// The source was deleted, as the destination has the same contents.
+    Delete,
+}
+
+/// A line of a run's journal file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Record {
+    operation: RecordedOperation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<PathBuf>,
+    destination: PathBuf,
+    /// The hash of the destination's contents, prefixed with the algorithm, as in `blake3:...`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hash: Option<String>,
+    /// Where the file that was overwritten is kept, so that it can be put back.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backup: Option<PathBuf>,
+    time: String,
+}
+
+/// The directory the journals of past runs are kept in: `$XDG_STATE_HOME/map/runs`, or
+/// `~/.local/state/map/runs` if that isn't set.
+pub fn runs_directory() -> Result<PathBuf> {
+    let state_home = match env::var_os("XDG_STATE_HOME").map(PathBuf::from) {
+        Some(ref directory) if directory.is_absolute() => directory.clone(),
+        _ => match env::var_os("HOME").or_else(|| env::var_os("USERPROFILE")) {
+            Some(home) => PathBuf::from(home).join(".local").join("state"),
+            None => bail!("Unable to find a directory to keep run journals in. Set XDG_STATE_HOME to choose one"),
+        },
+    };
+    Ok(state_home.join("map").join("runs"))
+}
+
+/// A new run ID, which sorts after those of earlier runs.
+pub fn new_run_id() -> String {
+    Local::now().format("%Y%m%d-%H%M%S-%3f").to_string()
+}
+
+/// Writes the operations of a run to `<directory>/<run_id>.jsonl`, with the hashes of the files they created so
+/// that they can be undone later. The files the run overwrote are kept in `<directory>/<run_id>.backups`.
+pub fn write_run(directory: &Path, run_id: &str, entries: &[Entry], algorithm: HashAlgorithm) -> Result<PathBuf> {
+    let backups = backups_path(directory, run_id);
+    let written = write_records(directory, run_id, &backups, entries, algorithm);
+    if written.is_err() && backups.exists() {
+        if let Err(error) = fs::remove_dir_all(&backups) {
+            error!("Unable to delete {}: {}", backups.to_string_lossy(), error);
+        }
+    }
+    written
+}
+
+fn write_records(directory: &Path, run_id: &str, backups: &Path, entries: &[Entry], algorithm: HashAlgorithm)
+                 -> Result<PathBuf> {
+    let mut lines = String::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let kept = backups.join(index.to_string());
+        lines += &serde_json::to_string(&record(entry, &kept, algorithm)?)
+            .chain_err(|| "Unable to serialize an operation")?;
+        lines.push('\n');
+    }
+    let path = journal_path(directory, run_id);
+    fs::create_dir_all(directory)
+        .and_then(|()| {
+            let mut file = File::create(&path)?;
+            file.write_all(lines.as_bytes())?;
+            file.sync_all()
+        })
+        .chain_err(|| format!("Unable to write the journal {}", path.to_string_lossy()))?;
+    Ok(path)
+}
+
+/// The record of `entry`. A file it replaced is copied to `kept`, as the run deletes it once it's committed.
+fn record(entry: &Entry, kept: &Path, algorithm: HashAlgorithm) -> Result<Record> {
+    let (operation, source, destination) = match entry.operation {
+        Operation::CreatedDirectory(ref directory) => (RecordedOperation::CreateDirectory, None, directory),
+        Operation::Copied { ref source, ref destination } => (RecordedOperation::Copy, Some(source), destination),
+        Operation::Linked { ref source, ref destination } => (RecordedOperation::Link, Some(source), destination),
+        Operation::ReplacedFile { ref destination, .. } => (RecordedOperation::Overwrite, None, destination),
+        Operation::Renamed { ref from, ref to } => (RecordedOperation::Move, Some(from), to),
+        Operation::DeleteOnCommit { ref file, ref duplicate } => (RecordedOperation::Delete, Some(file), duplicate),
+    };
+    // Symbolic links are recognised by what they are rather than by their contents
+    let hashed = operation != RecordedOperation::CreateDirectory && operation != RecordedOperation::Overwrite
+        && fs::symlink_metadata(destination).map(|metadata| metadata.is_file()).unwrap_or(false);
+    let hash = if hashed { Some(hash(destination, algorithm)?) } else { None };
+    let backup = match entry.operation {
+        Operation::ReplacedFile { ref backup, .. } => {
+            keep_backup(backup, kept)?;
+            Some(absolute(kept)?)
+        }
+        _ => None,
+    };
+    Ok(Record {
+        operation,
+        source: match source {
+            Some(source) => Some(absolute(source)?),
+            None => None,
+        },
+        destination: absolute(destination)?,
+        hash,
+        backup,
+        time: entry.time.to_rfc3339(),
+    })
+}
+
+/// Links or copies the `backup` of an overwritten file to `kept`, leaving `backup` for the run to roll back to.
+fn keep_backup(backup: &Path, kept: &Path) -> Result<()> {
+    create_parent(kept)?;
+    if fs::hard_link(backup, kept).is_ok() {
+        return Ok(());
+    }
+    copy_file(backup, kept, CopyMode::Auto)
+        .map(|_| ())
+        .chain_err(|| format!("Unable to keep a copy of overwritten file {}", backup.to_string_lossy()))
+}
+
+/// Reverses the run with ID `run_id`, or the latest run that hasn't been undone. Moved files are moved back,
+/// copies and links are deleted if they still have the contents they were created with, and created directories
+/// are removed if they're empty. The journal is kept if anything couldn't be undone, so that it can be tried again.
+pub fn undo_run(directory: &Path, run_id: Option<&str>) -> Result<()> {
+    let run_id = match run_id {
+        Some(run_id) => run_id.to_string(),
+        None => latest_run(directory)?,
+    };
+    let path = journal_path(directory, &run_id);
+    if !path.is_file() {
+        if undone_path(directory, &run_id).is_file() {
+            bail!("Run {} has already been undone", run_id);
+        }
+        bail!("Unable to find run {} in {}", run_id, directory.to_string_lossy());
+    }
+    let records = read_run(&path)?;
+    // A file that replaced one that can't be put back is kept rather than leave nothing at its destination
+    let unrestorable: Vec<&PathBuf> = records.iter()
+        .filter(|record| record.operation == RecordedOperation::Overwrite)
+        .filter(|record| !record.backup.as_ref().map(|backup| backup.is_file()).unwrap_or(false))
+        .map(|record| &record.destination)
+        .collect();
+
+    info!("Undoing run {}", run_id);
+    let mut failures = 0;
+    for record in records.iter().rev() {
+        if let Err(error) = undo(record, unrestorable.contains(&&record.destination)) {
+            error!("{}", error);
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        bail!("Unable to undo {} of the changes made by run {}", failures, run_id);
+    }
+    fs::rename(&path, undone_path(directory, &run_id))
+        .chain_err(|| format!("Undid run {}, but unable to mark its journal as undone", run_id))?;
+    let backups = backups_path(directory, &run_id);
+    if backups.exists() {
+        fs::remove_dir_all(&backups)
+            .chain_err(|| format!("Undid run {}, but unable to delete {}", run_id, backups.to_string_lossy()))?;
+    }
+    info!("Undid run {}", run_id);
+
+    Ok(())
+}
+
+fn read_run(path: &Path) -> Result<Vec<Record>> {
+    let file = File::open(path).chain_err(|| format!("Unable to open the journal {}", path.to_string_lossy()))?;
+    let mut records = vec![];
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.chain_err(|| format!("Unable to read the journal {}", path.to_string_lossy()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .chain_err(|| format!("Line {} of the journal {} is invalid", index + 1, path.to_string_lossy()))?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Undoes `record`. If `replaced_unrestorable`, a file it created replaced one that can't be put back, so it's kept.
+fn undo(record: &Record, replaced_unrestorable: bool) -> Result<()> {
+    let destination = &record.destination;
+    match record.operation {
+        RecordedOperation::CreateDirectory => {
+            if destination.is_dir() {
+                match fs::remove_dir(destination) {
+                    Ok(()) => info!("Removed directory {}", destination.to_string_lossy()),
+                    Err(_) => info!("Keeping directory {}, as it isn't empty", destination.to_string_lossy()),
+                }
+            }
+        }
+        RecordedOperation::Copy | RecordedOperation::Link => {
+            let metadata = match fs::symlink_metadata(destination) {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    debug!("{} is already gone", destination.to_string_lossy());
+                    return Ok(());
+                }
+            };
+            if replaced_unrestorable {
+                warn!("Keeping {}, as the file it replaced can't be restored", destination.to_string_lossy());
+                return Ok(());
+            }
+            let unchanged = if metadata.file_type().is_symlink() {
+                record.operation == RecordedOperation::Link
+            } else {
+                hash_matches(destination, &record.hash)?
+            };
+            if unchanged {
+                fs::remove_file(destination)
+                    .chain_err(|| format!("Unable to delete {}", destination.to_string_lossy()))?;
+                info!("Deleted {}", destination.to_string_lossy());
+            } else {
+                warn!("Keeping {}, as it has changed since it was mapped", destination.to_string_lossy());
+            }
+        }
+        RecordedOperation::Move => {
+            let source = source(record)?;
+            if source.exists() {
+                if !destination.exists() {
+                    debug!("{} has already been moved back", source.to_string_lossy());
+                    return Ok(());
+                }
+                bail!("Unable to move {} back, as {} exists", destination.to_string_lossy(), source.to_string_lossy());
+            }
+            if fs::symlink_metadata(destination).is_err() {
+                bail!("Unable to move {} back to {}, as it's no longer there", destination.to_string_lossy(),
+                      source.to_string_lossy());
+            }
+            create_parent(source)?;
+            move_back(destination, source)?;
+            info!("Moved {} back to {}", destination.to_string_lossy(), source.to_string_lossy());
+        }
+        RecordedOperation::Overwrite => {
+            let backup = match record.backup {
+                Some(ref backup) if backup.is_file() => backup,
+                _ => {
+                    warn!("Unable to restore the file that was overwritten at {}", destination.to_string_lossy());
+                    return Ok(());
+                }
+            };
+            if fs::symlink_metadata(destination).is_ok() {
+                bail!("Unable to restore the file that was overwritten at {}, as a file is there. It's kept at {}",
+                      destination.to_string_lossy(), backup.to_string_lossy());
+            }
+            create_parent(destination)?;
+            move_back(backup, destination)?;
+            info!("Restored the file that was overwritten at {}", destination.to_string_lossy());
+        }
+        RecordedOperation::Delete => {
+            let source = source(record)?;
+            if source.exists() {
+                debug!("{} has already been restored", source.to_string_lossy());
+                return Ok(());
+            }
+            if !destination.is_file() || !hash_matches(destination, &record.hash)? {
+                bail!("Unable to restore {}, as {} no longer has its contents", source.to_string_lossy(),
+                      destination.to_string_lossy());
+            }
+            create_parent(source)?;
+            copy_file(destination, source, CopyMode::Auto)
+                .chain_err(|| format!("Unable to restore {} from {}", source.to_string_lossy(),
+                                      destination.to_string_lossy()))?;
+            info!("Restored {} from {}", source.to_string_lossy(), destination.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+/// Renames `destination` to `source`, copying it and deleting it if they're on different devices.
+fn move_back(destination: &Path, source: &Path) -> Result<()> {
+    match fs::rename(destination, source) {
+        Ok(()) => Ok(()),
+        Err(ref error) if error.kind() == io::ErrorKind::CrossesDevices => {
+            let file_context = MapFileContext {
+                preserve: Preserve { times: true, mode: true, ..Preserve::default() },
+                ..MapFileContext::default()
+            };
+            copy_for_move(destination, source, &file_context)?;
+            fs::remove_file(destination).chain_err(|| format!("Copied {} back to {}, but unable to delete it",
+                                                              destination.to_string_lossy(), source.to_string_lossy()))
+        }
+        Err(error) => Err(error).chain_err(|| format!("Unable to move {} back to {}", destination.to_string_lossy(),
+                                                      source.to_string_lossy())),
+    }
+}
+
+fn source(record: &Record) -> Result<&PathBuf> {
+    record.source.as_ref()
+        .chain_err(|| format!("The {:?} of {} has no source", record.operation, record.destination.to_string_lossy()))
+}
+
+fn create_parent(file: &Path) -> Result<()> {
+    match file.parent() {
+        Some(parent) => fs::create_dir_all(parent)
+            .chain_err(|| format!("Unable to create directory {}", parent.to_string_lossy())),
+        None => Ok(()),
+    }
+}
+
+fn hash(file: &Path, algorithm: HashAlgorithm) -> Result<String> {
+    tagged_hash(file, algorithm).chain_err(|| format!("Unable to hash {}", file.to_string_lossy()))
+}
+
+/// Whether `file` still has the contents hashed in its record.
+fn hash_matches(file: &Path, recorded: &Option<String>) -> Result<bool> {
+    match *recorded {
+        Some(ref recorded) => has_tagged_hash(file, recorded)
+            .chain_err(|| format!("Unable to hash {}", file.to_string_lossy())),
+        None => Ok(false),
+    }
+}
+
+/// The absolute form of `path`, without resolving symbolic links, so that a run can be undone from anywhere.
+fn absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+    Ok(env::current_dir().chain_err(|| "Unable to find the current directory")?.join(path))
+}
+
+/// The ID of the latest run that hasn't been undone.
+fn latest_run(directory: &Path) -> Result<String> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => bail!("There are no runs to undo in {}", directory.to_string_lossy()),
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str().map(|name| name.to_string()))
+        .filter(|name| !name.ends_with(UNDONE_SUFFIX))
+        .filter_map(|name| name.strip_suffix(SUFFIX).map(|run_id| run_id.to_string()))
+        .max()
+        .chain_err(|| format!("There are no runs to undo in {}", directory.to_string_lossy()))
+}
+
+const SUFFIX: &str = ".jsonl";
+const UNDONE_SUFFIX: &str = ".undone.jsonl";
+
+fn journal_path(directory: &Path, run_id: &str) -> PathBuf {
+    directory.join(format!("{}{}", run_id, SUFFIX))
+}
+
+fn undone_path(directory: &Path, run_id: &str) -> PathBuf {
+    directory.join(format!("{}{}", run_id, UNDONE_SUFFIX))
+}
+
+fn backups_path(directory: &Path, run_id: &str) -> PathBuf {
+    directory.join(format!("{}.backups", run_id))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use journal::Journal;
+    use testutils::*;
+
+    #[test]
+    fn undo_run_reverses_operations() {
+        with_default_test_directory(|test_directory| {
+            let runs = test_directory.join("runs");
+            let source = test_directory.join("source");
+            let output = test_directory.join("output");
+            fs::create_dir(&source).unwrap();
+            fs::write(source.join("copied.txt"), "copied").unwrap();
+            fs::write(source.join("moved.txt"), "moved").unwrap();
+
+            let mut journal = Journal::default();
+            fs::create_dir(&output).unwrap();
+            journal.record(Operation::CreatedDirectory(output.clone()));
+            fs::copy(source.join("copied.txt"), output.join("copied.txt")).unwrap();
+            journal.record(Operation::Copied { source: source.join("copied.txt"), destination: output.join("copied.txt") });
+            fs::rename(source.join("moved.txt"), output.join("moved.txt")).unwrap();
+            journal.record(Operation::Renamed { from: source.join("moved.txt"), to: output.join("moved.txt") });
+            write_run(&runs, "1", journal.entries(), HashAlgorithm::Blake3).unwrap();
+
+            undo_run(&runs, None).unwrap();
+            assert!(!output.exists());
+            assert_eq!(fs::read_to_string(source.join("moved.txt")).unwrap(), "moved");
+            assert!(source.join("copied.txt").is_file());
+            assert!(undo_run(&runs, Some("1")).is_err());
+            assert!(undo_run(&runs, None).is_err());
+        });
+    }
+
+    #[test]
+    fn undo_run_keeps_changed_copies() {
+        with_default_test_directory(|test_directory| {
+            let runs = test_directory.join("runs");
+            let source = test_directory.join("source.txt");
+            let copy = test_directory.join("copy.txt");
+            fs::write(&source, "contents").unwrap();
+            fs::write(&copy, "contents").unwrap();
+
+            let mut journal = Journal::default();
+            journal.record(Operation::Copied { source: source.clone(), destination: copy.clone() });
+            write_run(&runs, "1", journal.entries(), HashAlgorithm::Sha256).unwrap();
+            fs::write(&copy, "changed").unwrap();
+
+            undo_run(&runs, Some("1")).unwrap();
+            assert_eq!(fs::read_to_string(&copy).unwrap(), "changed");
+        });
+    }
+
+    #[test]
+    fn undo_run_restores_deleted_duplicates() {
+        with_default_test_directory(|test_directory| {
+            let runs = test_directory.join("runs");
+            let source = test_directory.join("source.txt");
+            let duplicate = test_directory.join("duplicate.txt");
+            fs::write(&duplicate, "contents").unwrap();
+
+            let mut journal = Journal::default();
+            journal.record(Operation::DeleteOnCommit { file: source.clone(), duplicate: duplicate.clone() });
+            write_run(&runs, "1", journal.entries(), HashAlgorithm::Blake3).unwrap();
+
+            undo_run(&runs, None).unwrap();
+            assert_eq!(fs::read_to_string(&source).unwrap(), "contents");
+            assert!(duplicate.is_file());
+        });
+    }
+
+    #[test]
+    fn undo_run_restores_overwritten_files() {
+        with_default_test_directory(|test_directory| {
+            let runs = test_directory.join("runs");
+            let source = test_directory.join("source.txt");
+            let destination = test_directory.join("destination.txt");
+            fs::write(&source, "new").unwrap();
+            fs::write(&destination, "existing").unwrap();
+
+            let mut journal = Journal::default();
+            journal.replace_file(&destination).unwrap();
+            fs::copy(&source, &destination).unwrap();
+            journal.record(Operation::Copied { source: source.clone(), destination: destination.clone() });
+            write_run(&runs, "1", journal.entries(), HashAlgorithm::Blake3).unwrap();
+            journal.commit().unwrap();
+
+            undo_run(&runs, None).unwrap();
+            assert_eq!(fs::read_to_string(&destination).unwrap(), "existing");
+            assert_eq!(fs::read_to_string(&source).unwrap(), "new");
+            assert!(!backups_path(&runs, "1").exists());
+        });
+    }
+
+    #[test]
+    fn undo_run_keeps_copies_that_replaced_lost_files() {
+        with_default_test_directory(|test_directory| {
+            let runs = test_directory.join("runs");
+            let source = test_directory.join("source.txt");
+            let destination = test_directory.join("destination.txt");
+            fs::write(&source, "new").unwrap();
+            fs::write(&destination, "existing").unwrap();
+
+            let mut journal = Journal::default();
+            journal.replace_file(&destination).unwrap();
+            fs::copy(&source, &destination).unwrap();
+            journal.record(Operation::Copied { source: source.clone(), destination: destination.clone() });
+            write_run(&runs, "1", journal.entries(), HashAlgorithm::Blake3).unwrap();
+            journal.commit().unwrap();
+            fs::remove_dir_all(backups_path(&runs, "1")).unwrap();
+
+            undo_run(&runs, None).unwrap();
+            assert_eq!(fs::read_to_string(&destination).unwrap(), "new");
+        });
+    }
+
+    #[test]
+    fn latest_run_skips_undone_runs() {
+        with_default_test_directory(|test_directory| {
+            for name in &["20240101-000000-000.jsonl", "20250101-000000-000.jsonl", "20260101-000000-000.undone.jsonl"] {
+                fs::write(test_directory.join(name), "").unwrap();
+            }
+            assert_eq!(latest_run(test_directory).unwrap(), "20250101-000000-000");
+        });
+    }
+}
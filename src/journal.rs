@@ -0,0 +1,185 @@
+use chrono::{DateTime, Utc};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use error::*;
+
+/// A change made to the filesystem by a task.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    CreatedDirectory(PathBuf),
+    Copied { source: PathBuf, destination: PathBuf },
+    /// A symbolic or hard link to `source` was created at `destination`.
+    Linked { source: PathBuf, destination: PathBuf },
+    /// The file at `destination` was renamed to `backup` so that another could take its place.
+    ReplacedFile { destination: PathBuf, backup: PathBuf },
+    Renamed { from: PathBuf, to: PathBuf },
+    /// The file is to be deleted once all the tasks have succeeded, as `duplicate` has the same contents.
+    DeleteOnCommit { file: PathBuf, duplicate: PathBuf },
+}
+
+/// An operation and when it was made.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub operation: Operation,
+    pub time: DateTime<Utc>,
+}
+
+/// The changes made to the filesystem so far in a run, so that they can be undone if a later task fails.
+/// Files are only deleted once the run is committed, so undoing a change never needs the contents of a
+/// deleted file.
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub fn record(&mut self, operation: Operation) {
+        trace!("Recording {:?}", operation);
+        self.entries.push(Entry { operation, time: Utc::now() });
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Moves the file at `destination` aside so that it can be replaced, recording it so that it's put back if
+    /// the run is rolled back or deleted if it's committed.
+    pub fn replace_file(&mut self, destination: &Path) -> Result<()> {
+        let file_name = destination.file_name()
+            .chain_err(|| format!("Destination {} has no file name. This is a bug.", destination.to_string_lossy()))?;
+        let backup = destination.with_file_name(format!(".{}.map-backup", file_name.to_string_lossy()));
+        fs::rename(destination, &backup)
+            .chain_err(|| format!("Unable to move existing file {} aside", destination.to_string_lossy()))?;
+        self.record(Operation::ReplacedFile { destination: destination.to_path_buf(), backup });
+        Ok(())
+    }
+
+    /// Finishes the run by deleting the files that were replaced or moved across devices.
+    pub fn commit(self) -> Result<()> {
+        let mut failures = 0;
+        for entry in self.entries {
+            let deleted = match entry.operation {
+                Operation::ReplacedFile { backup, .. } => backup,
+                Operation::DeleteOnCommit { file, .. } => file,
+                _ => continue,
+            };
+            debug!("Deleting {}", deleted.to_string_lossy());
+            if let Err(error) = fs::remove_file(&deleted) {
+                error!("Unable to delete {}: {}", deleted.to_string_lossy(), error);
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            bail!("Unable to delete {} of the files that were mapped or replaced", failures);
+        }
+        Ok(())
+    }
+
+    /// The point a later `roll_back_to` returns to.
+    pub fn savepoint(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Undoes the changes made since `savepoint` in the reverse order they were made, carrying on past any that
+    /// can't be undone.
+    pub fn roll_back_to(&mut self, savepoint: usize) -> Result<()> {
+        let mut failures = 0;
+        while self.entries.len() > savepoint {
+            let entry = self.entries.pop().unwrap();
+            if let Err(error) = undo(&entry.operation) {
+                error!("Unable to undo {:?}: {}", entry.operation, error);
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            bail!("Unable to undo {} changes", failures);
+        }
+        Ok(())
+    }
+
+    /// Undoes every change made in the run.
+    pub fn roll_back(mut self) -> Result<()> {
+        if !self.entries.is_empty() {
+            warn!("Rolling back {} changes", self.entries.len());
+        }
+        self.roll_back_to(0)
+    }
+}
+
+fn undo(operation: &Operation) -> ::std::io::Result<()> {
+    match *operation {
+        Operation::CreatedDirectory(ref directory) => {
+            info!("Removing directory {}", directory.to_string_lossy());
+            fs::remove_dir(directory)
+        }
+        Operation::Copied { ref destination, .. } | Operation::Linked { ref destination, .. } => {
+            info!("Removing {}", destination.to_string_lossy());
+            // A failed copy may already have cleaned up after itself
+            match fs::remove_file(destination) {
+                Err(ref error) if error.kind() == ::std::io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        }
+        Operation::ReplacedFile { ref destination, ref backup } => {
+            info!("Restoring {}", destination.to_string_lossy());
+            fs::rename(backup, destination)
+        }
+        Operation::Renamed { ref from, ref to } => {
+            info!("Moving {} back to {}", to.to_string_lossy(), from.to_string_lossy());
+            fs::rename(to, from)
+        }
+        Operation::DeleteOnCommit { .. } => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use testutils::*;
+
+    #[test]
+    fn journal_roll_back_undoes_changes() {
+        with_default_test_directory(|test_directory| {
+            let directory = test_directory.join("created");
+            let created = directory.join("created.txt");
+            let existing = test_directory.join("existing.txt");
+            let moved = test_directory.join("moved.txt");
+            fs::write(&existing, "existing").unwrap();
+            fs::write(&moved, "moved").unwrap();
+
+            let mut journal = Journal::default();
+            fs::create_dir(&directory).unwrap();
+            journal.record(Operation::CreatedDirectory(directory.clone()));
+            fs::write(&created, "created").unwrap();
+            journal.record(Operation::Copied { source: moved.clone(), destination: created.clone() });
+            journal.replace_file(&existing).unwrap();
+            fs::rename(&moved, &existing).unwrap();
+            journal.record(Operation::Renamed { from: moved.clone(), to: existing.clone() });
+            journal.roll_back().unwrap();
+
+            assert!(!directory.exists());
+            assert_eq!(fs::read_to_string(&existing).unwrap(), "existing");
+            assert_eq!(fs::read_to_string(&moved).unwrap(), "moved");
+            assert_eq!(fs::read_dir(test_directory).unwrap().count(), 2);
+        });
+    }
+
+    #[test]
+    fn journal_commit_deletes_files() {
+        with_default_test_directory(|test_directory| {
+            let existing = test_directory.join("existing.txt");
+            let source = test_directory.join("source.txt");
+            fs::write(&existing, "existing").unwrap();
+            fs::write(&source, "source").unwrap();
+
+            let mut journal = Journal::default();
+            journal.replace_file(&existing).unwrap();
+            journal.record(Operation::DeleteOnCommit { file: source.clone(), duplicate: existing.clone() });
+            journal.commit().unwrap();
+
+            assert!(!source.exists());
+            assert_eq!(fs::read_dir(test_directory).unwrap().count(), 0);
+        });
+    }
+}
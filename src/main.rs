@@ -1,52 +1,97 @@
 // `error_chain!` can recurse deeply
 #![recursion_limit = "1024"]
 
+extern crate blake3;
+extern crate chrono;
 extern crate clap;
+extern crate exif;
+extern crate libc;
 extern crate regex;
+extern crate serde;
+extern crate serde_json;
+extern crate sha2;
+extern crate zip;
 #[macro_use]
 extern crate log;
 extern crate log4rs;
 #[macro_use]
 extern crate error_chain;
+#[macro_use]
+extern crate serde_derive;
 
 // For testing in submodules
 #[cfg(test)]
 extern crate rand;
 
-use clap::{App, Arg, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use log::LevelFilter;
 use log4rs::append::console::ConsoleAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
 
 mod action;
+mod audio;
+mod conflict;
+mod content_type;
 mod context;
+mod copy;
 mod directive;
+mod ebook;
 mod error;
+mod expression;
+mod glob;
+mod hash;
+mod history;
+mod journal;
 mod mapping;
+mod metadata;
+mod photo;
+mod plan;
+mod preserve;
+mod report;
 mod rule;
+mod summary;
+mod tags;
+mod template;
 
 #[cfg(test)]
 mod testutils;
 
 use action::*;
-use context::MapFileContext;
+use context::{
+    relative_path, ConflictPolicy, CopyMode, HashAlgorithm, LinkFallback, MapFileContext, MatchTarget, Preserve,
+    RuleConflictPolicy, TimeZone,
+};
 use directive::*;
 use error::*;
+use glob::Glob;
+use history::{new_run_id, runs_directory, undo_run, write_run};
+use journal::Journal;
+use plan::Plan;
 use mapping::*;
+use report::{render, ReportFormat};
+use summary::{files, Summary};
 
 use std::fs;
 use std::io::prelude::*;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{self, BufReader, IsTerminal};
+use std::path::{Path, PathBuf};
 
 fn main() {
     let argument_matches = create_app().get_matches();
 
-    configure_logging(argument_matches.occurrences_of("v"));
+    let (subcommand, subcommand_matches) = argument_matches.subcommand();
+    configure_logging(argument_matches.occurrences_of("v")
+        + subcommand_matches.map_or(0, |matches| matches.occurrences_of("v")));
 
     // If there was an error, nicely print it and the related causes
-    if let Err(ref error) = run(argument_matches) {
+    let result = match (subcommand, subcommand_matches) {
+        ("undo", Some(undo_matches)) => undo(undo_matches),
+        ("plan", Some(plan_matches)) => plan(&argument_matches, plan_matches),
+        ("apply", Some(apply_matches)) => apply(&argument_matches, apply_matches),
+        _ => run(&argument_matches),
+    };
+    if let Err(ref error) = result {
         error!("error: {}", error);
         for cause in error.iter().skip(1) {
             error!("caused by: {}", cause);
@@ -60,13 +105,51 @@ fn main() {
 fn create_app<'a, 'b>() -> App<'a, 'b> {
     // Feature ideas:
     // Delete behavior (don't do it (default), do it during, do it at end)
-    // Clobber behavior (don't do it and don't fail (default), don't do it and fail, do it)
     // Allow passing rules xor source file list
     // Copy in parallel
     App::new("map")
         .version("1.0")
         .author("Mitch S. <mitch+map@applicative.us>")
         .about("A program to copy files into folders based on name matches")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("undo")
+                .about("Reverses a past run, by default the latest that hasn't been undone")
+                .arg(
+                    Arg::with_name("run")
+                        .long("run")
+                        .value_name("ID")
+                        .help("Sets the ID of the run to undo, as logged at the end of the run")
+                        .takes_value(true),
+                )
+                .arg(verbosity()),
+        )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .about("Writes the tasks the rules would perform to a file to be reviewed and applied later")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Sets the file to write the plan to")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(verbosity()),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Performs the tasks in a plan, unless any of the files have changed since it was written")
+                .arg(
+                    Arg::with_name("plan")
+                        .value_name("PLAN")
+                        .help("Specifies the plan written by map plan")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(verbosity()),
+        )
         .arg(
             Arg::with_name("rules-file")
                 .short("r")
@@ -101,17 +184,152 @@ fn create_app<'a, 'b>() -> App<'a, 'b> {
                 .default_value(r".\"),
         )
         .arg(
-            Arg::with_name("v")
-                .short("v")
+            Arg::with_name("recursive")
+                .short("R")
+                .long("recursive")
+                .help("Sets whether to also look for files in subdirectories of the source directory"),
+        )
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .value_name("DEPTH")
+                .help("Sets how many levels of directories to look in when recursive, 1 being only the source directory")
+                .takes_value(true)
+                .requires("recursive"),
+        )
+        .arg(
+            Arg::with_name("include")
+                .long("include")
+                .value_name("GLOB")
+                .help("Only maps files whose name (or relative path, if the glob contains '/') matches the glob")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("exclude")
+                .long("exclude")
+                .value_name("GLOB")
+                .help("Skips files and directories whose name (or relative path, if the glob contains '/') matches the glob")
+                .takes_value(true)
                 .multiple(true)
-                .help("Sets the level of verbosity"),
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("match-on")
+                .long("match-on")
+                .value_name("TARGET")
+                .help("Sets whether rules match the file name or the path relative to the source directory")
+                .takes_value(true)
+                .possible_values(&["name", "path"])
+                .default_value("name"),
+        )
+        .arg(
+            Arg::with_name("utc")
+                .long("utc")
+                .help("Sets whether times in destination templates are written in UTC rather than local time"),
+        )
+        .arg(
+            Arg::with_name("on-conflict")
+                .long("on-conflict")
+                .value_name("POLICY")
+                .help("Sets what happens when a file is mapped to a destination that already exists")
+                .takes_value(true)
+                .possible_values(&["skip", "fail", "overwrite", "rename", "newer", "larger", "identical-skip"])
+                .default_value("overwrite"),
+        )
+        .arg(
+            Arg::with_name("conflicts")
+                .long("conflicts")
+                .value_name("POLICY")
+                .help("Sets which rules are applied to a file that matches more than one, or whether it's an error")
+                .takes_value(true)
+                .possible_values(&["first-wins", "last-wins", "error", "all"])
+                .default_value("error"),
+        )
+        .arg(
+            Arg::with_name("dedup")
+                .long("dedup")
+                .help("Sets whether files identical to their existing destination are treated as already mapped, deleting them if moved")
+        )
+        .arg(
+            Arg::with_name("hash")
+                .long("hash")
+                .value_name("ALGORITHM")
+                .help("Sets the hash used to compare the contents of files")
+                .takes_value(true)
+                .possible_values(&["blake3", "sha256"])
+                .default_value("blake3"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .help("Sets whether copies are read back and compared with the original by hash")
+        )
+        .arg(
+            Arg::with_name("preserve")
+                .long("preserve")
+                .value_name("ATTRIBUTES")
+                .help("Sets which comma-separated attributes of the original file copies keep, besides its permissions")
+                .takes_value(true)
+                .use_delimiter(true)
+                .possible_values(&["times", "mode", "owner", "xattr", "acl"]),
+        )
+        .arg(
+            Arg::with_name("copy-mode")
+                .long("copy-mode")
+                .value_name("MODE")
+                .help("Sets whether copies share data with the original file (reflink), never do (full), or do where the filesystem supports it (auto)")
+                .takes_value(true)
+                .possible_values(&["auto", "reflink", "full"])
+                .default_value("auto"),
         )
+        .arg(
+            Arg::with_name("link-fallback")
+                .long("link-fallback")
+                .value_name("FALLBACK")
+                .help("Sets whether files that can't be hard linked, as the destination is on a different device, are copied instead or fail")
+                .takes_value(true)
+                .possible_values(&["fail", "copy"])
+                .default_value("fail"),
+        )
+        .arg(
+            Arg::with_name("group")
+                .long("group")
+                .help("Sets whether files in the same directory with the same name but different extensions are mapped together"),
+        )
+        .arg(
+            Arg::with_name("prefer")
+                .long("prefer")
+                .value_name("EXTENSIONS")
+                .help("Only maps the first of these comma-separated extensions found in each group, e.g. epub,pdf,mobi")
+                .takes_value(true)
+                .use_delimiter(true)
+                .requires("group"),
+        )
+        .arg(verbosity())
         .arg(
             Arg::with_name("dry-run")
                 .short("n")
                 .long("dry-run")
                 .help("Sets whether or not to actually write to the filesystem"),
         )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .value_name("FORMAT")
+                .help("Shows what a dry run would do as the destination directory afterwards (tree) or a row for each file (table)")
+                .takes_value(true)
+                .possible_values(&["tree", "table"])
+                .requires("dry-run"),
+        )
+}
+
+fn verbosity<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("v")
+        .short("v")
+        .multiple(true)
+        .help("Sets the level of verbosity")
 }
 
 fn configure_logging(verbosity: u64) {
@@ -131,44 +349,212 @@ fn configure_logging(verbosity: u64) {
     log4rs::init_config(config).unwrap();
 }
 
-fn run(matches: ArgMatches) -> Result<()> {
-    let mappings: Vec<Mapping> = match matches.value_of("rules-file") {
-        Some(file) => mappings_from_file(&create_directives(), &PathBuf::from(file))?,
-        None => {
-            match mapping_from_string(&create_directives(), matches.value_of("rules-arg").unwrap())
-            {
-                Some(result) => vec![result?],
-                None => vec![],
-            }
-        }
+fn run(matches: &ArgMatches) -> Result<()> {
+    let file_context = file_context(matches)?;
+    let mut summary = Summary::default();
+    let tasks = tasks(matches, &file_context, &mut summary)?;
+    report(matches, &tasks, &file_context);
+    execute(tasks, &file_context, summary)
+}
+
+fn plan(matches: &ArgMatches, plan_matches: &ArgMatches) -> Result<()> {
+    let file_context = file_context(matches)?;
+    // Files left out of the plan are reported as they're found
+    let tasks = tasks(matches, &file_context, &mut Summary::default())?;
+    let plan = Plan::new(&tasks, &file_context.dest_dir, file_context.hash_algorithm)?;
+    // Safe to unwrap, as it's required
+    let output = PathBuf::from(plan_matches.value_of("output").unwrap());
+    plan.write(&output)?;
+    info!("Planned {} in {}. Use `map apply {}` to perform it", files(plan.tasks.len()), output.to_string_lossy(),
+          output.to_string_lossy());
+
+    Ok(())
+}
+
+fn apply(matches: &ArgMatches, apply_matches: &ArgMatches) -> Result<()> {
+    // Safe to unwrap, as it's required
+    let plan = Plan::read(Path::new(apply_matches.value_of("plan").unwrap()))?;
+    plan.check_sources()?;
+    plan.check_destinations()?;
+    let file_context = MapFileContext { dest_dir: plan.dest_dir.clone(), ..file_context(matches)? };
+    let tasks = plan.into_tasks();
+    report(matches, &tasks, &file_context);
+    execute(tasks, &file_context, Summary::default())
+}
+
+fn report(matches: &ArgMatches, tasks: &[MapFileTask], file_context: &MapFileContext) {
+    let format = match matches.value_of("report") {
+        Some("tree") => ReportFormat::Tree,
+        Some("table") => ReportFormat::Table,
+        _ => return,
     };
+    print!("{}", render(format, tasks, file_context, io::stdout().is_terminal()));
+}
 
+fn mappings(matches: &ArgMatches) -> Result<Vec<Mapping>> {
+    match matches.value_of("rules-file") {
+        Some(file) => mappings_from_file(&create_directives(), &PathBuf::from(file)),
+        None => match matches.value_of("rules-arg") {
+            Some(rule) => match mapping_from_string(&create_directives(), rule) {
+                Some(result) => Ok(vec![result?]),
+                None => Ok(vec![]),
+            },
+            None => bail!("No rules were given. Use --rules or pass a rule before the subcommand"),
+        },
+    }
+}
+
+fn file_context(matches: &ArgMatches) -> Result<MapFileContext> {
     let dry_run = matches.is_present("dry-run");
 
     // Safe to unwrap these, as we have defaults
     let source_dir = PathBuf::from(matches.value_of("source-dir").unwrap());
     let dest_dir = PathBuf::from(matches.value_of("dest-dir").unwrap());
 
-    let file_context = MapFileContext {
-        source_dir: source_dir.clone(),
-        dest_dir: dest_dir.clone(),
-        dry_run: dry_run,
+    let match_target = match matches.value_of("match-on") {
+        Some("path") => MatchTarget::RelativePath,
+        _ => MatchTarget::FileName,
+    };
+
+    let time_zone = if matches.is_present("utc") {
+        TimeZone::Utc
+    } else {
+        TimeZone::Local
+    };
+
+    let link_fallback = match matches.value_of("link-fallback") {
+        Some("copy") => LinkFallback::Copy,
+        _ => LinkFallback::Fail,
+    };
+
+    let copy_mode = match matches.value_of("copy-mode") {
+        Some("reflink") => CopyMode::Reflink,
+        Some("full") => CopyMode::Full,
+        _ => CopyMode::Auto,
+    };
+
+    let on_conflict = match matches.value_of("on-conflict") {
+        Some("skip") => ConflictPolicy::Skip,
+        Some("fail") => ConflictPolicy::Fail,
+        Some("rename") => ConflictPolicy::Rename,
+        Some("newer") => ConflictPolicy::Newer,
+        Some("larger") => ConflictPolicy::Larger,
+        Some("identical-skip") => ConflictPolicy::IdenticalSkip,
+        _ => ConflictPolicy::Overwrite,
+    };
+
+    let rule_conflicts = match matches.value_of("conflicts") {
+        Some("first-wins") => RuleConflictPolicy::FirstWins,
+        Some("last-wins") => RuleConflictPolicy::LastWins,
+        Some("all") => RuleConflictPolicy::All,
+        _ => RuleConflictPolicy::Error,
+    };
+
+    let hash_algorithm = match matches.value_of("hash") {
+        Some("sha256") => HashAlgorithm::Sha256,
+        _ => HashAlgorithm::Blake3,
     };
 
+    Ok(MapFileContext {
+        source_dir,
+        dest_dir,
+        dry_run: dry_run,
+        match_target,
+        time_zone,
+        link_fallback,
+        copy_mode,
+        on_conflict,
+        rule_conflicts,
+        dedup: matches.is_present("dedup"),
+        hash_algorithm,
+        verify: matches.is_present("verify"),
+        preserve: preserve(matches),
+    })
+}
+
+fn tasks<'a>(matches: &ArgMatches, file_context: &MapFileContext, summary: &mut Summary)
+    -> Result<Vec<MapFileTask<'a>>> {
+    let mappings = mappings(matches)?;
+    let traversal_options = traversal_options(matches)?;
+
     // Get all the paths that are files
-    let file_paths: Vec<PathBuf> = get_file_paths(&source_dir)?;
+    let file_paths: Vec<PathBuf> = get_file_paths(&file_context.source_dir, &traversal_options)?;
 
     // Get all the tasks for those files
-    let mut tasks: Vec<MapFileTask> = determine_tasks(&mappings, &file_paths, &file_context)?;
+    determine_tasks(&mappings, &file_paths, file_context, &grouping_options(matches), summary)
+}
 
-    // Execute all the tasks
+fn execute(mut tasks: Vec<MapFileTask>, file_context: &MapFileContext, mut summary: Summary) -> Result<()> {
+    // Execute all the tasks, undoing them all if one fails
+    let mut journal = Journal::default();
     while let Some(task) = tasks.pop() {
-        task.execute(&file_context)?;
+        match task.execute(file_context, &mut journal) {
+            Ok(outcome) => summary.record(&outcome),
+            Err(error) => {
+                // Reported before the files mapped so far are rolled back, so that the summary shows the mismatch
+                if let ErrorKind::VerificationFailed(..) = *error.kind() {
+                    summary.record_verification_failure();
+                    summary.log();
+                }
+                if let Err(rollback_error) = journal.roll_back() {
+                    error!("{}", rollback_error);
+                }
+                return Err(error);
+            }
+        }
     }
+    // Written before anything is deleted, so that the run can still be rolled back if it can't be
+    if !journal.entries().is_empty() {
+        let run_id = new_run_id();
+        let written = runs_directory()
+            .and_then(|directory| write_run(&directory, &run_id, journal.entries(), file_context.hash_algorithm));
+        match written {
+            Ok(path) => info!("Recorded run {} in {}. Use `map undo --run {}` to undo it", run_id,
+                              path.to_string_lossy(), run_id),
+            Err(error) => {
+                if let Err(rollback_error) = journal.roll_back() {
+                    error!("{}", rollback_error);
+                }
+                return Err(error);
+            }
+        }
+    }
+    journal.commit()?;
+    summary.log();
 
     Ok(())
 }
 
+fn undo(matches: &ArgMatches) -> Result<()> {
+    undo_run(&runs_directory()?, matches.value_of("run"))
+}
+
+fn preserve(matches: &ArgMatches) -> Preserve {
+    let attributes: Vec<&str> = matches.values_of("preserve").map(|values| values.collect()).unwrap_or_default();
+    Preserve {
+        times: attributes.contains(&"times"),
+        mode: attributes.contains(&"mode"),
+        owner: attributes.contains(&"owner"),
+        xattr: attributes.contains(&"xattr"),
+        acl: attributes.contains(&"acl"),
+    }
+}
+
+fn grouping_options(matches: &ArgMatches) -> GroupingOptions {
+    let preferred_formats = match matches.values_of("prefer") {
+        Some(values) => values
+            .map(|value| value.trim_start_matches('.').to_lowercase())
+            .filter(|value| !value.is_empty())
+            .collect(),
+        None => vec![],
+    };
+
+    GroupingOptions {
+        group: matches.is_present("group"),
+        preferred_formats,
+    }
+}
+
 fn mappings_from_file(
     all_directives: &Vec<Box<dyn MappingDirective>>,
     file: &PathBuf,
@@ -176,20 +562,102 @@ fn mappings_from_file(
     let f = fs::File::open(file)
         .chain_err(|| format!("Unable to open rules file {}", file.to_string_lossy()))?;
     let mut mappings = vec![];
-    for line_result in BufReader::new(f).lines() {
+    let mut invalid_rules = vec![];
+    for (index, line_result) in BufReader::new(f).lines().enumerate() {
         let line = line_result
             .chain_err(|| format!("Error reading rules file {}", file.to_string_lossy()))?;
         match mapping_from_string(all_directives, &line) {
-            Some(result) => mappings.push(result?),
+            Some(Ok(mapping)) => mappings.push(mapping.with_line(index + 1)),
+            Some(Err(error)) => {
+                let causes: Vec<String> = error.iter().map(|cause| cause.to_string()).collect();
+                invalid_rules.push(format!("  line {}: {}", index + 1, causes.join(": ")));
+            }
             None => (),
         };
     }
+    // Report every invalid rule at once, so that a large rules file can be fixed in one go
+    if !invalid_rules.is_empty() {
+        bail!("Rules file {} has invalid rules\n{}", file.to_string_lossy(), invalid_rules.join("\n"));
+    }
 
     Ok(mappings)
 }
 
-fn get_file_paths(directory: &PathBuf) -> Result<Vec<PathBuf>> {
+/// Controls which files beneath the source directory are considered for mapping.
+#[derive(Default)]
+struct TraversalOptions {
+    recursive: bool,
+    max_depth: Option<usize>,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+}
+
+impl TraversalOptions {
+    fn is_included(&self, relative_path: &str) -> bool {
+        self.include.is_empty() || self.include.iter().any(|glob| glob_matches(glob, relative_path))
+    }
+
+    fn is_excluded(&self, relative_path: &str) -> bool {
+        self.exclude.iter().any(|glob| glob_matches(glob, relative_path))
+    }
+
+    fn can_descend(&self, depth: usize) -> bool {
+        self.recursive && self.max_depth.is_none_or(|max_depth| depth < max_depth)
+    }
+}
+
+/// Globs containing a `/` are matched against the relative path, others against the last component.
+fn glob_matches(glob: &Glob, relative_path: &str) -> bool {
+    if glob.has_path_separator() {
+        glob.is_match(relative_path)
+    } else {
+        glob.is_match(relative_path.rsplit('/').next().unwrap_or(relative_path))
+    }
+}
+
+fn traversal_options(matches: &ArgMatches) -> Result<TraversalOptions> {
+    let max_depth = match matches.value_of("max-depth") {
+        Some(depth) => Some(
+            depth
+                .parse::<usize>()
+                .chain_err(|| format!("Invalid maximum depth {}", depth))?,
+        ),
+        None => None,
+    };
+
+    Ok(TraversalOptions {
+        recursive: matches.is_present("recursive"),
+        max_depth,
+        include: globs_from_arguments(matches, "include")?,
+        exclude: globs_from_arguments(matches, "exclude")?,
+    })
+}
+
+fn globs_from_arguments(matches: &ArgMatches, argument: &str) -> Result<Vec<Glob>> {
+    let mut globs = vec![];
+    if let Some(values) = matches.values_of(argument) {
+        for value in values {
+            globs.push(Glob::new(value).chain_err(|| format!("Invalid --{} glob {}", argument, value))?);
+        }
+    }
+
+    Ok(globs)
+}
+
+fn get_file_paths(directory: &Path, options: &TraversalOptions) -> Result<Vec<PathBuf>> {
     let mut file_paths: Vec<PathBuf> = Vec::new();
+    collect_file_paths(directory, directory, 1, options, &mut file_paths)?;
+
+    Ok(file_paths)
+}
+
+fn collect_file_paths(
+    source_dir: &Path,
+    directory: &Path,
+    depth: usize,
+    options: &TraversalOptions,
+    file_paths: &mut Vec<PathBuf>,
+) -> Result<()> {
     for entry in fs::read_dir(directory).chain_err(|| {
         format!(
             "Unable to read entries of directory {}",
@@ -203,15 +671,30 @@ fn get_file_paths(directory: &PathBuf) -> Result<Vec<PathBuf>> {
             )
         })?;
         let file_path = dir_entry.path();
-        if dir_entry.path().is_file() {
-            trace!("Regular file: {}", file_path.to_string_lossy());
-            file_paths.push(dir_entry.path());
+        let relative_path = relative_path(source_dir, &file_path);
+        if options.is_excluded(&relative_path) {
+            trace!("Excluded: {}", file_path.to_string_lossy());
+        } else if file_path.is_file() {
+            if options.is_included(&relative_path) {
+                trace!("Regular file: {}", file_path.to_string_lossy());
+                file_paths.push(file_path);
+            } else {
+                trace!("Not included: {}", file_path.to_string_lossy());
+            }
+        } else if is_directory(&dir_entry) && options.can_descend(depth) {
+            trace!("Descending into directory: {}", file_path.to_string_lossy());
+            collect_file_paths(source_dir, &file_path, depth + 1, options, file_paths)?;
         } else {
             trace!("Not a file: {}", file_path.to_string_lossy());
         }
     }
 
-    Ok(file_paths)
+    Ok(())
+}
+
+/// Whether the entry is a directory, without following symlinks so that recursion can't loop.
+fn is_directory(dir_entry: &fs::DirEntry) -> bool {
+    dir_entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false)
 }
 
 #[cfg(test)]
@@ -224,7 +707,7 @@ mod test {
 
     #[test]
     fn get_file_paths_dir_does_not_exist() {
-        match get_file_paths(&PathBuf::from("does-not-exist")) {
+        match get_file_paths(&PathBuf::from("does-not-exist"), &TraversalOptions::default()) {
             Ok(_) => panic!("No results should be returned"),
             Err(_) => (),
         }
@@ -233,7 +716,7 @@ mod test {
     #[test]
     fn get_file_paths_no_files() {
         with_default_test_directory(|test_directory| {
-            let paths: Vec<PathBuf> = get_file_paths(test_directory).unwrap();
+            let paths: Vec<PathBuf> = get_file_paths(test_directory, &TraversalOptions::default()).unwrap();
             assert_eq!(paths.len(), 0);
         });
     }
@@ -243,11 +726,66 @@ mod test {
         with_default_test_directory(|test_directory| {
             with_test_directory(&test_directory.join("not-a-file"), |_inner_directory| {
                 with_default_test_file(test_directory, |test_file| {
-                    let mut paths: Vec<PathBuf> = get_file_paths(test_directory).unwrap();
+                    let mut paths: Vec<PathBuf> = get_file_paths(test_directory, &TraversalOptions::default()).unwrap();
                     assert_eq!(paths.len(), 1);
                     assert_eq!(&paths.pop().unwrap(), test_file);
                 })
             })
         });
     }
+
+    #[test]
+    fn get_file_paths_recursive() {
+        with_default_test_directory(|test_directory| {
+            with_test_directory(&test_directory.join("nested"), |inner_directory| {
+                with_default_test_file(inner_directory, |test_file| {
+                    let options = TraversalOptions {
+                        recursive: true,
+                        ..Default::default()
+                    };
+                    let paths: Vec<PathBuf> = get_file_paths(test_directory, &options).unwrap();
+                    assert_eq!(paths, vec![test_file.clone()]);
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn get_file_paths_recursive_max_depth() {
+        with_default_test_directory(|test_directory| {
+            with_test_directory(&test_directory.join("nested"), |inner_directory| {
+                with_default_test_file(inner_directory, |_test_file| {
+                    let options = TraversalOptions {
+                        recursive: true,
+                        max_depth: Some(1),
+                        ..Default::default()
+                    };
+                    let paths: Vec<PathBuf> = get_file_paths(test_directory, &options).unwrap();
+                    assert_eq!(paths.len(), 0);
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn get_file_paths_include_and_exclude() {
+        with_default_test_directory(|test_directory| {
+            with_test_directory(&test_directory.join("skipped"), |skipped_directory| {
+                with_test_file(&skipped_directory.join("book.epub"), |_skipped_file| {
+                    with_test_file(&test_directory.join("book.epub"), |included_file| {
+                        with_test_file(&test_directory.join("book.pdf"), |_not_included_file| {
+                            let options = TraversalOptions {
+                                recursive: true,
+                                include: vec![Glob::new("*.epub").unwrap()],
+                                exclude: vec![Glob::new("skipped").unwrap()],
+                                ..Default::default()
+                            };
+                            let paths: Vec<PathBuf> = get_file_paths(test_directory, &options).unwrap();
+                            assert_eq!(paths, vec![included_file.clone()]);
+                        })
+                    })
+                })
+            })
+        });
+    }
 }
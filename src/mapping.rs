@@ -1,62 +1,301 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 use rule::MapRule;
-use action::{MapAction, MapFileTask};
-use context::MapFileContext;
+use action::{ActionKind, MapAction, MapFileTask};
+use context::{MapFileContext, RuleConflictPolicy};
 use error::*;
+use summary::{files, Summary};
 
 pub struct Mapping {
     rule: Box<dyn MapRule>,
-    action: Box<dyn MapAction>
+    action: Box<dyn MapAction>,
+    /// The line of the rules file the mapping was read from, if it was read from one.
+    line: Option<usize>
 }
 
 impl Mapping {
     pub fn new(rule: Box<dyn MapRule>, action: Box<dyn MapAction>) -> Mapping {
-        Mapping { rule, action }
+        Mapping { rule, action, line: None }
     }
+
+    pub fn with_line(mut self, line: usize) -> Mapping {
+        self.line = Some(line);
+        self
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{:?} (line {})", self.rule, line),
+            None => write!(f, "{:?}", self.rule),
+        }
+    }
+}
+
+/// How files that are the same document in different formats, e.g. `aboutface.pdf` and `aboutface.epub`, are
+/// mapped.
+#[derive(Clone, Debug, Default)]
+pub struct GroupingOptions {
+    /// Whether files in the same directory with the same normalised stem are grouped, so that a single
+    /// mapping decision applies to the whole group.
+    pub group: bool,
+    /// Extensions in order of preference. If any are given, only the most preferred format of each group
+    /// is mapped.
+    pub preferred_formats: Vec<String>,
 }
 
-pub fn determine_tasks<'a>(mappings: &Vec<Mapping>, files: &Vec<PathBuf>, file_context: &MapFileContext) -> Result<Vec<MapFileTask<'a>>> {
+impl GroupingOptions {
+    /// Where the extension of `file` is in the list of preferred formats, if it's in it at all.
+    fn format_rank(&self, file: &Path) -> Option<usize> {
+        let extension = file.extension()?.to_string_lossy().to_lowercase();
+        self.preferred_formats.iter().position(|format| format == &extension)
+    }
+}
+
+/// The tasks that map `files`. Files left out in favour of a preferred format are counted in `summary`.
+pub fn determine_tasks<'a>(mappings: &[Mapping], files: &[PathBuf], file_context: &MapFileContext,
+                           grouping: &GroupingOptions, summary: &mut Summary) -> Result<Vec<MapFileTask<'a>>> {
     let mut tasks: Vec<MapFileTask<'static>> = Vec::new();
-    for file_path in files {
-        let task: Option<MapFileTask<'static>> = determine_task(&mappings, file_path.clone(), file_context.clone())?;
-        match task {
-            Some(function) => tasks.push(function),
-            None => debug!("No rule matches for file: {}", file_path.to_string_lossy())
+    let mut conflicts = Conflicts::default();
+    for group in group_files(files, grouping) {
+        for (file_path, function) in determine_group_tasks(mappings, &group, file_context, grouping, &mut conflicts,
+                                                           summary)? {
+            if let Some(destination) = function.destination() {
+                conflicts.add_destination(destination, file_path);
+            }
+            tasks.push(function)
         }
     }
+    conflicts.check()?;
 
     Ok(tasks)
 }
 
-fn determine_task<'a>(mappings: &Vec<Mapping>, file: PathBuf, file_context: MapFileContext) -> Result<Option<MapFileTask<'a>>> {
-    let mut task: Option<MapFileTask<'a>> = None;
-    let mut found_mapping: Option<&Mapping> = None;
-    for mapping in mappings {
-        if mapping.rule.file_matches_rule(&file, &file_context) {
-            if task.is_none() {
-                task = Some(mapping.action.create_task(file.clone()));
-                found_mapping = Some(&mapping);
-            } else {
-                bail!("Duplicate rules {:?} and {:?} match file {}", found_mapping.unwrap().rule,
-                      mapping.rule, file.to_string_lossy())
+/// Files matched by more than one rule, files more than one rule would move and destinations more than one file
+/// would be mapped to, collected so that they can all be reported at once rather than one per run.
+#[derive(Default)]
+struct Conflicts<'f> {
+    rules: Vec<(&'f PathBuf, String)>,
+    moves: Vec<(&'f PathBuf, String)>,
+    destinations: Vec<(PathBuf, Vec<&'f PathBuf>)>,
+    destination_indices: HashMap<PathBuf, usize>,
+}
+
+impl<'f> Conflicts<'f> {
+    fn add_rules(&mut self, file: &'f PathBuf, mappings: &[&Mapping]) {
+        let rules: Vec<String> = mappings.iter().map(|mapping| mapping.to_string()).collect();
+        self.rules.push((file, rules.join(", ")));
+    }
+
+    fn add_moves(&mut self, file: &'f PathBuf, mappings: &[&Mapping]) {
+        let rules: Vec<String> = mappings.iter().map(|mapping| mapping.to_string()).collect();
+        self.moves.push((file, rules.join(", ")));
+    }
+
+    fn add_destination(&mut self, destination: &Path, file: &'f PathBuf) {
+        match self.destination_indices.get(destination) {
+            Some(&index) => self.destinations[index].1.push(file),
+            None => {
+                self.destination_indices.insert(destination.to_path_buf(), self.destinations.len());
+                self.destinations.push((destination.to_path_buf(), vec![file]));
+            }
+        }
+    }
+
+    /// Fails with every conflict, if there are any.
+    fn check(&self) -> Result<()> {
+        let mut report = vec![];
+        if !self.rules.is_empty() {
+            report.push(format!("{} matched by more than one rule, which --conflicts can choose between:",
+                                files(self.rules.len())));
+            for (file, rules) in &self.rules {
+                report.push(format!("  {}: {}", file.to_string_lossy(), rules));
+            }
+        }
+        if !self.moves.is_empty() {
+            report.push(format!("{} would be moved by more than one rule, but can only be moved once:",
+                                files(self.moves.len())));
+            for (file, rules) in &self.moves {
+                report.push(format!("  {}: {}", file.to_string_lossy(), rules));
+            }
+        }
+        let destinations: Vec<&(PathBuf, Vec<&PathBuf>)> = self.destinations.iter()
+            .filter(|(_, files)| files.len() > 1)
+            .collect();
+        if !destinations.is_empty() {
+            let count = match destinations.len() {
+                1 => "1 destination would".to_string(),
+                count => format!("{} destinations would", count),
+            };
+            report.push(format!("{} receive more than one file:", count));
+            for (destination, files) in destinations {
+                let files: Vec<String> = files.iter().map(|file| file.to_string_lossy().into_owned()).collect();
+                report.push(format!("  {}: {}", destination.to_string_lossy(), files.join(", ")));
+            }
+        }
+        if !report.is_empty() {
+            bail!("Found conflicting rules\n{}", report.join("\n"));
+        }
+        Ok(())
+    }
+}
+
+/// Splits `files` into groups that share a mapping decision, keeping the order of the files. Without
+/// grouping, each file is in a group of its own.
+fn group_files<'f>(files: &'f [PathBuf], grouping: &GroupingOptions) -> Vec<Vec<&'f PathBuf>> {
+    if !grouping.group {
+        return files.iter().map(|file| vec![file]).collect();
+    }
+    let mut groups: Vec<Vec<&PathBuf>> = Vec::new();
+    let mut group_indices: HashMap<(Option<&Path>, String), usize> = HashMap::new();
+    for file in files {
+        let key = (file.parent(), normalised_stem(file));
+        match group_indices.get(&key) {
+            Some(&index) => groups[index].push(file),
+            None => {
+                group_indices.insert(key, groups.len());
+                groups.push(vec![file]);
+            }
+        }
+    }
+
+    groups
+}
+
+/// The stem of `file` in lower case and without punctuation, so that `About Face.pdf` and `aboutface.epub`
+/// are grouped together.
+fn normalised_stem(file: &Path) -> String {
+    let stem: String = file.file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+        .chars()
+        .filter(|character| character.is_alphanumeric())
+        .collect();
+    if stem.is_empty() {
+        // Don't group files whose names are all punctuation
+        file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+    } else {
+        stem
+    }
+}
+
+/// Maps a group of files with the first mapping that matches one of them, trying the files in order of
+/// preference. The captures of that file's match are used for the whole group.
+fn determine_group_tasks<'a, 'f>(mappings: &[Mapping], group: &[&'f PathBuf], file_context: &MapFileContext,
+                                 grouping: &GroupingOptions, conflicts: &mut Conflicts<'f>, summary: &mut Summary)
+    -> Result<Vec<(&'f PathBuf, MapFileTask<'a>)>> {
+    let mut ordered_group = group.to_vec();
+    ordered_group.sort_by_key(|file| grouping.format_rank(file).unwrap_or(usize::MAX));
+
+    let mut decision: Option<(&PathBuf, Vec<&Mapping>)> = None;
+    for &file in &ordered_group {
+        let matching = find_mappings(mappings, file, file_context);
+        if matching.is_empty() {
+            continue;
+        }
+        match choose_mappings(&matching, file, file_context.rule_conflicts) {
+            Some(chosen) => decision = Some((file, chosen)),
+            None => {
+                conflicts.add_rules(file, &matching);
+                return Ok(vec![]);
             }
         }
+        break;
+    }
+    let (matched_file, chosen_mappings) = match decision {
+        Some(decision) => decision,
+        None => {
+            for file in group {
+                debug!("No rule matches for file: {}", file.to_string_lossy());
+            }
+            return Ok(vec![]);
+        }
+    };
+
+    let preferred_file = ordered_group[0];
+    let members = if grouping.format_rank(preferred_file).is_some() {
+        for file in &ordered_group[1..] {
+            warn!("Not mapping {}, as {} is the preferred format", file.to_string_lossy(),
+                  preferred_file.to_string_lossy());
+            summary.record_not_preferred();
+        }
+        vec![preferred_file]
+    } else {
+        ordered_group
+    };
+    for file in &members {
+        if *file != matched_file {
+            debug!("Mapping {} along with {}", file.to_string_lossy(), matched_file.to_string_lossy());
+        }
+    }
+
+    let mut tasks = Vec::with_capacity(members.len() * chosen_mappings.len());
+    let mut moving_mappings: Vec<&Mapping> = vec![];
+    // Tasks are run from the last, so this applies the rules in the order they're written and moves files only
+    // once every other rule has been applied to them
+    for mapping in chosen_mappings.into_iter().rev() {
+        let variables = mapping.rule.captures(matched_file, file_context);
+        let mut moves = false;
+        for &file in &members {
+            let task = mapping.action.create_task(file.clone(), &variables, file_context)?;
+            moves |= task.description().is_some_and(|description| description.action == ActionKind::Move);
+            tasks.push((file, task.with_rule(format!("{:?}", mapping.rule))));
+        }
+        if moves {
+            moving_mappings.insert(0, mapping);
+        }
+    }
+    // Once a file is moved, there's nothing left for another rule to move
+    if moving_mappings.len() > 1 {
+        for &file in &members {
+            conflicts.add_moves(file, &moving_mappings);
+        }
+        return Ok(vec![]);
     }
+    tasks.sort_by_key(|(_, task)| task.description().is_none_or(|description| description.action != ActionKind::Move));
 
-    Ok(task)
+    Ok(tasks)
+}
+
+fn find_mappings<'m>(mappings: &'m [Mapping], file: &PathBuf, file_context: &MapFileContext) -> Vec<&'m Mapping> {
+    mappings.iter()
+        .filter(|mapping| mapping.rule.file_matches_rule(file, file_context))
+        .collect()
+}
+
+/// Which of the mappings matching `file` are applied to it, or none if more than one matching is an error.
+fn choose_mappings<'m>(matching: &[&'m Mapping], file: &Path, policy: RuleConflictPolicy)
+    -> Option<Vec<&'m Mapping>> {
+    let chosen = match policy {
+        _ if matching.len() == 1 => return Some(matching.to_vec()),
+        RuleConflictPolicy::Error => return None,
+        RuleConflictPolicy::FirstWins => matching[0],
+        RuleConflictPolicy::LastWins => matching[matching.len() - 1],
+        RuleConflictPolicy::All => return Some(matching.to_vec()),
+    };
+    let rules: Vec<String> = matching.iter().map(|mapping| mapping.to_string()).collect();
+    info!("Rules {} all match file {}, so only {} is applied", rules.join(", "), file.to_string_lossy(), chosen);
+    Some(vec![chosen])
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use action::TaskDescription;
+    use journal::Journal;
+    use summary::Outcome;
+    use template::TemplateVariables;
     use testutils::*;
 
     #[test]
     fn determine_tasks_no_mappings() {
         with_default_test_directory(|test_directory| {
             with_default_test_file(test_directory, |test_file| {
-                let tasks = determine_tasks(&vec![], &vec![test_file.clone()], &dummy_map_file_context()).unwrap();
+                let files = vec![test_file.clone()];
+                let tasks = determine_tasks(&[], &files, &dummy_map_file_context(), &GroupingOptions::default(), &mut Summary::default()).unwrap();
                 assert_eq!(tasks.len(), 0);
             })
         });
@@ -67,10 +306,11 @@ mod test {
         let mappings = vec![
             Mapping {
                 rule: Box::new(TestMapRule(PathBuf::from("not-used"))),
-                action: Box::new(TestMapAction())
+                action: Box::new(TestMapAction()),
+                line: None
             }
         ];
-        let tasks = determine_tasks(&mappings, &vec![], &dummy_map_file_context()).unwrap();
+        let tasks = determine_tasks(&mappings, &[], &dummy_map_file_context(), &GroupingOptions::default(), &mut Summary::default()).unwrap();
         assert_eq!(tasks.len(), 0);
     }
 
@@ -81,16 +321,19 @@ mod test {
                 let mappings = vec![
                     Mapping {
                         rule: Box::new(TestMapRule(PathBuf::from("does-not-match"))),
-                        action: Box::new(TestErrorMapAction())
+                        action: Box::new(TestErrorMapAction()),
+                        line: None
                     },
                     Mapping {
                         rule: Box::new(TestMapRule(test_file.clone())),
-                        action: Box::new(TestMapAction())
+                        action: Box::new(TestMapAction()),
+                        line: None
                     }
                 ];
-                let mut tasks = determine_tasks(&mappings, &vec![test_file.clone()], &dummy_map_file_context()).unwrap();
+                let files = vec![test_file.clone()];
+                let mut tasks = determine_tasks(&mappings, &files, &dummy_map_file_context(), &GroupingOptions::default(), &mut Summary::default()).unwrap();
                 assert_eq!(tasks.len(), 1);
-                assert_eq!(tasks.pop().unwrap().execute(&dummy_map_file_context()).is_ok(), true);
+                assert_eq!(tasks.pop().unwrap().execute(&dummy_map_file_context(), &mut Journal::default()).is_ok(), true);
             })
         });
     }
@@ -102,20 +345,184 @@ mod test {
                 let mappings = vec![
                     Mapping {
                         rule: Box::new(TestMapRule(test_file.clone())),
-                        action: Box::new(TestMapAction())
+                        action: Box::new(TestMapAction()),
+                        line: None
                     },
                     Mapping {
                         rule: Box::new(TestMapRule(test_file.clone())),
-                        action: Box::new(TestMapAction())
+                        action: Box::new(TestMapAction()),
+                        line: None
                     }
                 ];
 
-                let tasks = determine_tasks(&mappings, &vec![test_file.clone()], &dummy_map_file_context());
+                let files = vec![test_file.clone()];
+                let tasks = determine_tasks(&mappings, &files, &dummy_map_file_context(), &GroupingOptions::default(), &mut Summary::default());
                 assert_eq!(tasks.is_err(), true);
             })
         });
     }
 
+    #[test]
+    fn determine_tasks_overlapping_mappings_policies() {
+        let file = PathBuf::from("lime.txt");
+        let mappings = vec![
+            Mapping::new(Box::new(TestMapRule(file.clone())), Box::new(TestMoveMapAction(PathBuf::from("moved")))),
+            Mapping::new(Box::new(TestMapRule(file.clone())), Box::new(TestDestinationMapAction(PathBuf::from("copied")))),
+        ];
+        let files = vec![file];
+        let determine = |rule_conflicts| {
+            let file_context = MapFileContext { rule_conflicts, ..dummy_map_file_context() };
+            destinations(&determine_tasks(&mappings, &files, &file_context, &GroupingOptions::default(), &mut Summary::default()).unwrap())
+        };
+
+        assert_eq!(determine(RuleConflictPolicy::FirstWins), vec![PathBuf::from("moved")]);
+        assert_eq!(determine(RuleConflictPolicy::LastWins), vec![PathBuf::from("copied")]);
+        // Run from the last, so the file is copied before it's moved
+        assert_eq!(determine(RuleConflictPolicy::All), vec![PathBuf::from("moved"), PathBuf::from("copied")]);
+
+        // Other rules are applied in the order they're written
+        let mappings = vec![
+            Mapping::new(Box::new(TestMapRule(files[0].clone())), Box::new(TestDestinationMapAction(PathBuf::from("first")))),
+            Mapping::new(Box::new(TestMapRule(files[0].clone())), Box::new(TestDestinationMapAction(PathBuf::from("second")))),
+        ];
+        let file_context = MapFileContext { rule_conflicts: RuleConflictPolicy::All, ..dummy_map_file_context() };
+        let tasks = determine_tasks(&mappings, &files, &file_context, &GroupingOptions::default(), &mut Summary::default()).unwrap();
+        assert_eq!(destinations(&tasks), vec![PathBuf::from("second"), PathBuf::from("first")]);
+    }
+
+    #[test]
+    fn determine_tasks_reports_every_conflict() {
+        let first = PathBuf::from("first.txt");
+        let second = PathBuf::from("second.txt");
+        let mappings = vec![
+            Mapping::new(Box::new(TestMapRule(first.clone())), Box::new(TestMapAction())).with_line(1),
+            Mapping::new(Box::new(TestMapRule(first.clone())), Box::new(TestMapAction())).with_line(2),
+            Mapping::new(Box::new(TestMapRule(second.clone())), Box::new(TestMapAction())).with_line(3),
+            Mapping::new(Box::new(TestMapRule(second.clone())), Box::new(TestMapAction())).with_line(5),
+        ];
+        let files = vec![first, second];
+
+        let error = determine_tasks(&mappings, &files, &dummy_map_file_context(), &GroupingOptions::default(), &mut Summary::default())
+            .err().unwrap();
+        assert_eq!(error.to_string(), "Found conflicting rules
+2 files matched by more than one rule, which --conflicts can choose between:
+  first.txt: TestMapRule(\"first.txt\") (line 1), TestMapRule(\"first.txt\") (line 2)
+  second.txt: TestMapRule(\"second.txt\") (line 3), TestMapRule(\"second.txt\") (line 5)");
+    }
+
+    #[test]
+    fn determine_tasks_reports_files_moved_by_more_than_one_rule() {
+        let file = PathBuf::from("lime.txt");
+        let mappings = vec![
+            Mapping::new(Box::new(TestMapRule(file.clone())), Box::new(TestMoveMapAction(PathBuf::from("first"))))
+                .with_line(1),
+            Mapping::new(Box::new(TestMapRule(file.clone())), Box::new(TestDestinationMapAction(PathBuf::from("copied"))))
+                .with_line(2),
+            Mapping::new(Box::new(TestMapRule(file.clone())), Box::new(TestMoveMapAction(PathBuf::from("second"))))
+                .with_line(3),
+        ];
+        let file_context = MapFileContext { rule_conflicts: RuleConflictPolicy::All, ..dummy_map_file_context() };
+
+        let error = determine_tasks(&mappings, &[file], &file_context, &GroupingOptions::default(), &mut Summary::default())
+            .err().unwrap();
+        assert_eq!(error.to_string(), "Found conflicting rules
+1 file would be moved by more than one rule, but can only be moved once:
+  lime.txt: TestMapRule(\"lime.txt\") (line 1), TestMapRule(\"lime.txt\") (line 3)");
+    }
+
+    #[test]
+    fn determine_tasks_colliding_destinations() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |first_file| {
+                with_default_test_file(test_directory, |second_file| {
+                    let mappings = vec![
+                        Mapping {
+                            rule: Box::new(TestMapRule(first_file.clone())),
+                            action: Box::new(TestDestinationMapAction(PathBuf::from("same"))),
+                            line: None
+                        },
+                        Mapping {
+                            rule: Box::new(TestMapRule(second_file.clone())),
+                            action: Box::new(TestDestinationMapAction(PathBuf::from("same"))),
+                            line: None
+                        }
+                    ];
+
+                    let files = vec![first_file.clone(), second_file.clone()];
+                    let tasks = determine_tasks(&mappings, &files, &dummy_map_file_context(), &GroupingOptions::default(), &mut Summary::default());
+                    assert!(tasks.err().unwrap().to_string().contains("\n1 destination would receive more than one file:\n"));
+                })
+            })
+        });
+    }
+
+    #[test]
+    fn determine_tasks_groups_formats() {
+        let pdf = PathBuf::from("books/About Face.pdf");
+        let epub = PathBuf::from("books/aboutface.epub");
+        let other = PathBuf::from("books/other.epub");
+        let mappings = vec![
+            Mapping {
+                rule: Box::new(TestMapRule(pdf.clone())),
+                action: Box::new(TestFileNameMapAction()),
+                line: None
+            }
+        ];
+        let files = vec![pdf.clone(), epub.clone(), other.clone()];
+
+        let ungrouped = determine_tasks(&mappings, &files, &dummy_map_file_context(), &GroupingOptions::default(), &mut Summary::default()).unwrap();
+        assert_eq!(destinations(&ungrouped), vec![PathBuf::from("About Face.pdf")]);
+
+        let grouping = GroupingOptions { group: true, ..GroupingOptions::default() };
+        let grouped = determine_tasks(&mappings, &files, &dummy_map_file_context(), &grouping, &mut Summary::default()).unwrap();
+        assert_eq!(destinations(&grouped), vec![PathBuf::from("About Face.pdf"), PathBuf::from("aboutface.epub")]);
+    }
+
+    #[test]
+    fn determine_tasks_groups_only_within_directory() {
+        let first = PathBuf::from("first/book.pdf");
+        let second = PathBuf::from("second/book.epub");
+        let mappings = vec![
+            Mapping {
+                rule: Box::new(TestMapRule(first.clone())),
+                action: Box::new(TestFileNameMapAction()),
+                line: None
+            }
+        ];
+        let grouping = GroupingOptions { group: true, ..GroupingOptions::default() };
+        let tasks = determine_tasks(&mappings, &[first, second], &dummy_map_file_context(), &grouping, &mut Summary::default()).unwrap();
+        assert_eq!(destinations(&tasks), vec![PathBuf::from("book.pdf")]);
+    }
+
+    #[test]
+    fn determine_tasks_preferred_format() {
+        let pdf = PathBuf::from("book.pdf");
+        let epub = PathBuf::from("book.epub");
+        let mobi = PathBuf::from("book.mobi");
+        let mappings = vec![
+            Mapping {
+                rule: Box::new(TestMapRule(pdf.clone())),
+                action: Box::new(TestFileNameMapAction()),
+                line: None
+            }
+        ];
+        let files = vec![pdf, epub, mobi];
+
+        let grouping = GroupingOptions { group: true, preferred_formats: vec!["epub".to_string(), "pdf".to_string()] };
+        let mut summary = Summary::default();
+        let tasks = determine_tasks(&mappings, &files, &dummy_map_file_context(), &grouping, &mut summary).unwrap();
+        assert_eq!(destinations(&tasks), vec![PathBuf::from("book.epub")]);
+        assert_eq!(summary.to_string(), "Mapped 0 files and left 2 files in favour of a preferred format");
+
+        let grouping = GroupingOptions { group: true, preferred_formats: vec!["azw3".to_string()] };
+        let tasks = determine_tasks(&mappings, &files, &dummy_map_file_context(), &grouping, &mut Summary::default()).unwrap();
+        assert_eq!(tasks.len(), 3);
+    }
+
+    fn destinations(tasks: &[MapFileTask]) -> Vec<PathBuf> {
+        tasks.iter().filter_map(|task| task.destination().cloned()).collect()
+    }
+
     #[derive(Debug)]
     struct TestMapRule(PathBuf);
 
@@ -127,16 +534,48 @@ mod test {
 
     struct TestMapAction();
     struct TestErrorMapAction();
+    struct TestDestinationMapAction(PathBuf);
+    struct TestFileNameMapAction();
+    struct TestMoveMapAction(PathBuf);
 
     impl MapAction for TestMapAction {
-        fn create_task<'a>(&self, _file: PathBuf) -> MapFileTask<'a> {
-            MapFileTask::new(|_file_context| Ok(()))
+        fn create_task<'a>(&self, _file: PathBuf, _variables: &TemplateVariables, _file_context: &MapFileContext)
+            -> Result<MapFileTask<'a>> {
+            Ok(MapFileTask::new(|_file_context, _journal| Ok(Outcome::Mapped)))
         }
     }
 
     impl MapAction for TestErrorMapAction {
-        fn create_task<'a>(&self, _file: PathBuf) -> MapFileTask<'a> {
-            MapFileTask::new(|_file_context| bail!("Always returns an error"))
+        fn create_task<'a>(&self, _file: PathBuf, _variables: &TemplateVariables, _file_context: &MapFileContext)
+            -> Result<MapFileTask<'a>> {
+            Ok(MapFileTask::new(|_file_context, _journal| bail!("Always returns an error")))
+        }
+    }
+
+    impl MapAction for TestDestinationMapAction {
+        fn create_task<'a>(&self, file: PathBuf, _variables: &TemplateVariables, _file_context: &MapFileContext)
+            -> Result<MapFileTask<'a>> {
+            Ok(MapFileTask::new(|_file_context, _journal| Ok(Outcome::Mapped)).with_description(description(file, self.0.clone())))
+        }
+    }
+
+    impl MapAction for TestFileNameMapAction {
+        fn create_task<'a>(&self, file: PathBuf, _variables: &TemplateVariables, _file_context: &MapFileContext)
+            -> Result<MapFileTask<'a>> {
+            let destination = PathBuf::from(file.file_name().unwrap());
+            Ok(MapFileTask::new(|_file_context, _journal| Ok(Outcome::Mapped)).with_description(description(file, destination)))
+        }
+    }
+
+    impl MapAction for TestMoveMapAction {
+        fn create_task<'a>(&self, file: PathBuf, _variables: &TemplateVariables, _file_context: &MapFileContext)
+            -> Result<MapFileTask<'a>> {
+            let description = TaskDescription { action: ActionKind::Move, ..description(file, self.0.clone()) };
+            Ok(MapFileTask::new(|_file_context, _journal| Ok(Outcome::Mapped)).with_description(description))
         }
     }
-}
\ No newline at end of file
+
+    fn description(source: PathBuf, destination: PathBuf) -> TaskDescription {
+        TaskDescription { action: ActionKind::Copy, source, destination, rule: String::new() }
+    }
+}
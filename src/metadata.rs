@@ -0,0 +1,587 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use context::MapFileContext;
+use error::*;
+use photo::PhotoMetadata;
+use rule::MapRule;
+
+/// How a predicate compares a file's property to the value in the rule, e.g. the `>` in `size>10MB`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+    Colon,
+}
+
+impl Operator {
+    /// Parses the operator at the start of `text`, returning it and its length.
+    pub fn parse(text: &str) -> Option<(Operator, usize)> {
+        let operators = [
+            ("<=", Operator::LessOrEqual),
+            (">=", Operator::GreaterOrEqual),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+            ("=", Operator::Equal),
+            (":", Operator::Colon),
+        ];
+        operators
+            .iter()
+            .find(|(symbol, _)| text.starts_with(symbol))
+            .map(|&(symbol, operator)| (operator, symbol.len()))
+    }
+
+    fn compare<T: PartialOrd>(self, actual: T, expected: T) -> bool {
+        match self {
+            Operator::Less => actual < expected,
+            Operator::LessOrEqual => actual <= expected,
+            Operator::Equal | Operator::Colon => actual == expected,
+            Operator::GreaterOrEqual => actual >= expected,
+            Operator::Greater => actual > expected,
+        }
+    }
+
+    fn is_comparison(self) -> bool {
+        self != Operator::Colon
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::Equal => "=",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Greater => ">",
+            Operator::Colon => ":",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Matches files by their size, e.g. `size>10MB`.
+pub struct SizeRule {
+    operator: Operator,
+    size: u64,
+    source: String,
+}
+
+impl SizeRule {
+    pub fn parse(operator: Option<Operator>, value: &str) -> Result<SizeRule> {
+        let operator = comparison_operator("size", operator)?;
+        Ok(SizeRule {
+            operator,
+            size: parse_size(value)?,
+            source: value.to_string(),
+        })
+    }
+}
+
+impl MapRule for SizeRule {
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        match fs::metadata(file) {
+            Ok(metadata) => self.operator.compare(metadata.len(), self.size),
+            Err(error) => {
+                warn!("Unable to read size of {}: {}", file.to_string_lossy(), error);
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SizeRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "size{}{}", self.operator, self.source)
+    }
+}
+
+/// Which of a file's timestamps a rule or template refers to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeKind {
+    Modified,
+    /// When the file's inode last changed, as on Unix. This includes changes to its permissions or owner.
+    Changed,
+    /// When the file was created, which not every filesystem records.
+    Created,
+    Accessed,
+    /// When a photo was taken according to its EXIF data, or when it was modified if it has none.
+    Taken,
+}
+
+impl TimeKind {
+    pub fn from_name(name: &str) -> Option<TimeKind> {
+        match name {
+            "mtime" => Some(TimeKind::Modified),
+            "ctime" => Some(TimeKind::Changed),
+            "btime" => Some(TimeKind::Created),
+            "atime" => Some(TimeKind::Accessed),
+            "taken" => Some(TimeKind::Taken),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeKind::Modified => "mtime",
+            TimeKind::Changed => "ctime",
+            TimeKind::Created => "btime",
+            TimeKind::Accessed => "atime",
+            TimeKind::Taken => "taken",
+        }
+    }
+
+    pub fn of(self, file: &Path) -> Result<SystemTime> {
+        if self == TimeKind::Taken {
+            let taken = PhotoMetadata::read(file)?
+                .and_then(|photo| photo.taken())
+                .and_then(|taken| Local.from_local_datetime(&taken).earliest());
+            return match taken {
+                Some(taken) => Ok(SystemTime::from(taken)),
+                None => TimeKind::Modified.of(file),
+            };
+        }
+        let metadata = fs::metadata(file)
+            .chain_err(|| format!("Unable to read metadata of {}", file.to_string_lossy()))?;
+        let time = match self {
+            TimeKind::Modified => metadata.modified(),
+            TimeKind::Changed => changed(&metadata),
+            TimeKind::Created => metadata.created(),
+            TimeKind::Accessed => metadata.accessed(),
+            TimeKind::Taken => unreachable!(),
+        };
+        time.chain_err(|| format!("Unable to read {} of {}", self.name(), file.to_string_lossy()))
+    }
+}
+
+#[cfg(unix)]
+fn changed(metadata: &fs::Metadata) -> ::std::io::Result<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+    use std::time::UNIX_EPOCH;
+
+    let since_epoch = Duration::new(metadata.ctime().unsigned_abs(), metadata.ctime_nsec() as u32);
+    Ok(if metadata.ctime() >= 0 { UNIX_EPOCH + since_epoch } else { UNIX_EPOCH - since_epoch })
+}
+
+#[cfg(not(unix))]
+fn changed(_metadata: &fs::Metadata) -> ::std::io::Result<SystemTime> {
+    Err(::std::io::Error::new(::std::io::ErrorKind::Other, "Inode change times are only available on Unix"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TimeThreshold {
+    /// Compares how long ago the timestamp was, e.g. `mtime>90d` for files older than 90 days.
+    Age(Duration),
+    /// Compares the timestamp itself, e.g. `mtime>2024-01-01` for files modified after the start of 2024.
+    Instant(SystemTime),
+}
+
+/// Matches files by one of their timestamps, e.g. `mtime>90d` or `ctime<2024-01-01`.
+pub struct TimeRule {
+    kind: TimeKind,
+    operator: Operator,
+    threshold: TimeThreshold,
+    now: SystemTime,
+    source: String,
+}
+
+impl TimeRule {
+    pub fn parse(kind: TimeKind, operator: Option<Operator>, value: &str) -> Result<TimeRule> {
+        let operator = comparison_operator(kind.name(), operator)?;
+        let threshold = match parse_duration(value)? {
+            Some(duration) => TimeThreshold::Age(duration),
+            None => TimeThreshold::Instant(parse_local_time(value)?),
+        };
+        Ok(TimeRule {
+            kind,
+            operator,
+            threshold,
+            now: SystemTime::now(),
+            source: value.to_string(),
+        })
+    }
+}
+
+impl MapRule for TimeRule {
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        let time = match self.kind.of(file) {
+            Ok(time) => time,
+            Err(error) => {
+                warn!("{}", error);
+                return false;
+            }
+        };
+        match self.threshold {
+            TimeThreshold::Age(age) => {
+                let file_age = self.now.duration_since(time).unwrap_or_default();
+                self.operator.compare(file_age, age)
+            }
+            TimeThreshold::Instant(instant) => self.operator.compare(time, instant),
+        }
+    }
+}
+
+impl fmt::Debug for TimeRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}", self.kind.name(), self.operator, self.source)
+    }
+}
+
+/// Matches files that can't be written to.
+pub struct ReadOnlyRule;
+
+impl MapRule for ReadOnlyRule {
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        fs::metadata(file)
+            .map(|metadata| metadata.permissions().readonly())
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Debug for ReadOnlyRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "readonly")
+    }
+}
+
+/// Matches files whose permissions include all of the given bits, e.g. `mode:111` for files that
+/// everyone may execute.
+pub struct ModeRule {
+    mode: u32,
+}
+
+impl ModeRule {
+    pub fn parse(operator: Option<Operator>, value: &str) -> Result<ModeRule> {
+        if operator != Some(Operator::Colon) {
+            bail!("Expected mode:<octal permissions>, found mode{}", format_operator(operator));
+        }
+        let mode = u32::from_str_radix(value, 8).chain_err(|| format!("Invalid octal permissions {}", value))?;
+        Ok(ModeRule { mode })
+    }
+}
+
+impl MapRule for ModeRule {
+    #[cfg(unix)]
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::metadata(file)
+            .map(|metadata| metadata.permissions().mode() & self.mode == self.mode)
+            .unwrap_or(false)
+    }
+
+    #[cfg(not(unix))]
+    fn file_matches_rule(&self, _file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        false
+    }
+}
+
+impl fmt::Debug for ModeRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mode:{:o}", self.mode)
+    }
+}
+
+/// Matches files owned by the given user, by name or numeric id, e.g. `owner:mitch`.
+pub struct OwnerRule {
+    uid: u32,
+    source: String,
+}
+
+impl OwnerRule {
+    pub fn parse(operator: Option<Operator>, value: &str) -> Result<OwnerRule> {
+        if operator != Some(Operator::Colon) {
+            bail!("Expected owner:<user>, found owner{}", format_operator(operator));
+        }
+        let uid = match value.parse::<u32>() {
+            Ok(uid) => uid,
+            Err(_) => match user_id(value) {
+                Some(uid) => uid,
+                None => bail!("Unknown user {}", value),
+            },
+        };
+        Ok(OwnerRule {
+            uid,
+            source: value.to_string(),
+        })
+    }
+}
+
+impl MapRule for OwnerRule {
+    #[cfg(unix)]
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        use std::os::unix::fs::MetadataExt;
+
+        fs::metadata(file)
+            .map(|metadata| metadata.uid() == self.uid)
+            .unwrap_or(false)
+    }
+
+    #[cfg(not(unix))]
+    fn file_matches_rule(&self, _file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        false
+    }
+}
+
+impl fmt::Debug for OwnerRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "owner:{}", self.source)
+    }
+}
+
+#[cfg(unix)]
+fn user_id(name: &str) -> Option<u32> {
+    use libc;
+    use std::ffi::CString;
+    use std::mem;
+    use std::ptr;
+
+    let name = CString::new(name).ok()?;
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut password: libc::passwd = unsafe { mem::zeroed() };
+    let mut result: *mut libc::passwd = ptr::null_mut();
+    let status = unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut password,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if status == 0 && !result.is_null() {
+        Some(password.pw_uid)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn user_id(_name: &str) -> Option<u32> {
+    None
+}
+
+fn comparison_operator(name: &str, operator: Option<Operator>) -> Result<Operator> {
+    match operator {
+        Some(operator) if operator.is_comparison() => Ok(operator),
+        _ => bail!("Expected one of <, <=, =, >=, > after {}, found {}{}", name, name, format_operator(operator)),
+    }
+}
+
+pub fn format_operator(operator: Option<Operator>) -> String {
+    operator.map(|operator| operator.to_string()).unwrap_or_default()
+}
+
+/// Parses sizes like `512`, `10MB`, `1.5GiB`. Suffixes without an `i` are powers of 1000, those with
+/// one are powers of 1024.
+fn parse_size(value: &str) -> Result<u64> {
+    let split = value
+        .find(|character: char| !(character.is_ascii_digit() || character == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().chain_err(|| format!("Invalid size {}", value))?;
+    let multiplier: u64 = match unit.to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1000,
+        "M" | "MB" => 1000u64.pow(2),
+        "G" | "GB" => 1000u64.pow(3),
+        "T" | "TB" => 1000u64.pow(4),
+        "KIB" => 1024,
+        "MIB" => 1024u64.pow(2),
+        "GIB" => 1024u64.pow(3),
+        "TIB" => 1024u64.pow(4),
+        _ => bail!("Invalid size unit {} in {}", unit, value),
+    };
+
+    let size = number * multiplier as f64;
+    // Converting to an integer would quietly clamp sizes that don't fit
+    if size >= u64::MAX as f64 {
+        bail!("Invalid size {}, as it's too large", value);
+    }
+
+    Ok(size as u64)
+}
+
+/// Parses durations like `30d` or `12h`, returning `None` if `value` isn't a duration.
+fn parse_duration(value: &str) -> Result<Option<Duration>> {
+    let split = match value.find(|character: char| !character.is_ascii_digit()) {
+        Some(split) if split > 0 => split,
+        _ => return Ok(None),
+    };
+    let (number, unit) = value.split_at(split);
+    let seconds: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        "y" => 365 * 24 * 60 * 60,
+        _ => return Ok(None),
+    };
+    match number.parse::<u64>().ok().and_then(|number| number.checked_mul(seconds)) {
+        Some(seconds) => Ok(Some(Duration::from_secs(seconds))),
+        None => bail!("Invalid duration {}, as it's too long", value),
+    }
+}
+
+/// Parses local dates like `2024-01-01` (the start of that day) or `2024-01-01T12:30[:00]`.
+fn parse_local_time(value: &str) -> Result<SystemTime> {
+    let date_time = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M"))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })
+        .chain_err(|| format!("Invalid duration or date {}", value))?;
+    match Local.from_local_datetime(&date_time).earliest() {
+        Some(local_time) => Ok(SystemTime::from(local_time)),
+        None => bail!("Date {} does not exist in the local time zone", value),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use testutils::*;
+
+    #[test]
+    fn parse_size_units() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("10MB").unwrap(), 10_000_000);
+        assert_eq!(parse_size("1.5KiB").unwrap(), 1536);
+        assert_eq!(parse_size("2gib").unwrap(), 2 * 1024 * 1024 * 1024);
+        assert!(parse_size("10XB").is_err());
+        assert!(parse_size("MB").is_err());
+        assert!(parse_size("20000000000TB").is_err());
+        assert!(parse_size("18446744073709551616").is_err());
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("30d").unwrap(), Some(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(parse_duration("12h").unwrap(), Some(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_duration("2024-01-01").unwrap(), None);
+        assert_eq!(parse_duration("d").unwrap(), None);
+        assert!(parse_duration("9999999999999999d").is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+        assert!(TimeRule::parse(TimeKind::Modified, Some(Operator::Greater), "9999999999999999d").is_err());
+    }
+
+    #[test]
+    fn parse_local_time_formats() {
+        assert!(parse_local_time("2024-01-01").is_ok());
+        assert!(parse_local_time("2024-01-01T12:30").is_ok());
+        assert!(parse_local_time("2024-01-01T12:30:15").is_ok());
+        assert!(parse_local_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn size_rule_compares_file_size() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                File::create(test_file).unwrap().write_all(&[0; 2048]).unwrap();
+                let file_context = dummy_map_file_context();
+                let larger = SizeRule::parse(Some(Operator::Greater), "2KB").unwrap();
+                let smaller = SizeRule::parse(Some(Operator::Less), "2KiB").unwrap();
+                assert!(larger.file_matches_rule(test_file, &file_context));
+                assert!(!smaller.file_matches_rule(test_file, &file_context));
+            })
+        });
+    }
+
+    #[test]
+    fn size_rule_requires_comparison() {
+        assert!(SizeRule::parse(Some(Operator::Colon), "2KB").is_err());
+        assert!(SizeRule::parse(None, "2KB").is_err());
+    }
+
+    #[test]
+    fn time_rule_compares_age_and_date() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let file_context = dummy_map_file_context();
+                let older = TimeRule::parse(TimeKind::Modified, Some(Operator::Greater), "1d").unwrap();
+                let newer = TimeRule::parse(TimeKind::Modified, Some(Operator::Less), "1d").unwrap();
+                let after = TimeRule::parse(TimeKind::Modified, Some(Operator::Greater), "2000-01-01").unwrap();
+                assert!(!older.file_matches_rule(test_file, &file_context));
+                assert!(newer.file_matches_rule(test_file, &file_context));
+                assert!(after.file_matches_rule(test_file, &file_context));
+            })
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn time_kind_ctime_is_inode_change_time() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::set_permissions(test_file, fs::Permissions::from_mode(0o600)).unwrap();
+                let metadata = fs::metadata(test_file).unwrap();
+                let changed = TimeKind::from_name("ctime").unwrap().of(test_file).unwrap();
+                let since_epoch = changed.duration_since(::std::time::UNIX_EPOCH).unwrap();
+                assert_eq!(since_epoch.as_secs() as i64, metadata.ctime());
+                assert_eq!(since_epoch.subsec_nanos() as i64, metadata.ctime_nsec());
+                assert_eq!(TimeKind::from_name("btime"), Some(TimeKind::Created));
+            })
+        });
+    }
+
+    #[test]
+    fn time_rule_missing_file() {
+        let rule = TimeRule::parse(TimeKind::Modified, Some(Operator::Less), "1d").unwrap();
+        assert!(!rule.file_matches_rule(&PathBuf::from("does-not-exist"), &dummy_map_file_context()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mode_rule_matches_permission_bits() {
+        use std::os::unix::fs::PermissionsExt;
+
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::set_permissions(test_file, fs::Permissions::from_mode(0o640)).unwrap();
+                let file_context = dummy_map_file_context();
+                let readable = ModeRule::parse(Some(Operator::Colon), "640").unwrap();
+                let executable = ModeRule::parse(Some(Operator::Colon), "100").unwrap();
+                assert!(readable.file_matches_rule(test_file, &file_context));
+                assert!(!executable.file_matches_rule(test_file, &file_context));
+            })
+        });
+    }
+
+    #[test]
+    fn mode_rule_invalid_mode() {
+        assert!(ModeRule::parse(Some(Operator::Colon), "rwx").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn owner_rule_matches_numeric_owner() {
+        use std::os::unix::fs::MetadataExt;
+
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let uid = fs::metadata(test_file).unwrap().uid();
+                let rule = OwnerRule::parse(Some(Operator::Colon), &uid.to_string()).unwrap();
+                assert!(rule.file_matches_rule(test_file, &dummy_map_file_context()));
+            })
+        });
+    }
+
+    #[test]
+    fn owner_rule_unknown_user() {
+        assert!(OwnerRule::parse(Some(Operator::Colon), "no-such-user-for-map").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{Context, Exif, In, Reader, Tag, Value};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use context::MapFileContext;
+use error::*;
+use metadata::{format_operator, Operator};
+use rule::MapRule;
+
+/// The EXIF data of a photo, as stored in JPEG, TIFF, HEIC, PNG and WebP files.
+pub struct PhotoMetadata {
+    exif: Exif,
+}
+
+impl PhotoMetadata {
+    /// Reads the EXIF data of `file`, or returns `None` if it isn't an image with EXIF data.
+    pub fn read(file: &Path) -> Result<Option<PhotoMetadata>> {
+        let opened = File::open(file).chain_err(|| format!("Unable to open {}", file.to_string_lossy()))?;
+        match Reader::new().read_from_container(&mut BufReader::new(opened)) {
+            Ok(exif) => Ok(Some(PhotoMetadata { exif })),
+            Err(error) => {
+                trace!("No EXIF data in {}: {}", file.to_string_lossy(), error);
+                Ok(None)
+            }
+        }
+    }
+
+    /// When the photo was taken, in the camera's local time.
+    pub fn taken(&self) -> Option<NaiveDateTime> {
+        [Tag::DateTimeOriginal, Tag::DateTimeDigitized, Tag::DateTime]
+            .iter()
+            .filter_map(|&tag| self.text(tag))
+            .filter_map(|text| exif::DateTime::from_ascii(text.as_bytes()).ok())
+            .filter_map(|time| {
+                NaiveDate::from_ymd_opt(i32::from(time.year), u32::from(time.month), u32::from(time.day))
+                    .and_then(|date| date.and_hms_opt(u32::from(time.hour), u32::from(time.minute), u32::from(time.second)))
+            })
+            .next()
+    }
+
+    pub fn make(&self) -> Option<String> {
+        self.text(Tag::Make)
+    }
+
+    pub fn model(&self) -> Option<String> {
+        self.text(Tag::Model)
+    }
+
+    /// The make and model of the camera, e.g. `Canon EOS R6`. Many cameras already start their model
+    /// with the make, in which case it isn't repeated.
+    pub fn camera(&self) -> Option<String> {
+        match (self.make(), self.model()) {
+            (Some(make), Some(model)) => {
+                if model.to_lowercase().starts_with(&make.to_lowercase()) {
+                    Some(model)
+                } else {
+                    Some(format!("{} {}", make, model))
+                }
+            }
+            (make, model) => make.or(model),
+        }
+    }
+
+    pub fn has_gps(&self) -> bool {
+        self.exif.fields().any(|field| field.tag.context() == Context::Gps)
+    }
+
+    fn text(&self, tag: Tag) -> Option<String> {
+        let field = self.exif.get_field(tag, In::PRIMARY)?;
+        match field.value {
+            Value::Ascii(ref values) => values
+                .first()
+                .map(|value| String::from_utf8_lossy(value).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string())
+                .filter(|value| !value.is_empty()),
+            _ => None,
+        }
+    }
+}
+
+/// What a photo's EXIF data must include for `exif:<property>` to match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ExifProperty {
+    Any,
+    Date,
+    Camera,
+    Gps,
+}
+
+/// Matches photos with EXIF data, e.g. `exif` or `exif:gps`.
+pub struct ExifRule {
+    property: ExifProperty,
+}
+
+impl ExifRule {
+    pub fn parse(operator: Option<Operator>, value: &str) -> Result<ExifRule> {
+        let property = match (operator, value) {
+            (None, _) => ExifProperty::Any,
+            (Some(Operator::Colon), "date") => ExifProperty::Date,
+            (Some(Operator::Colon), "camera") => ExifProperty::Camera,
+            (Some(Operator::Colon), "gps") => ExifProperty::Gps,
+            (Some(Operator::Colon), _) => bail!("Unknown EXIF property {}. Known properties are date, camera and gps", value),
+            (operator, _) => bail!("Expected exif or exif:<property>, found exif{}", format_operator(operator)),
+        };
+
+        Ok(ExifRule { property })
+    }
+}
+
+impl MapRule for ExifRule {
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        let photo = match PhotoMetadata::read(file) {
+            Ok(Some(photo)) => photo,
+            Ok(None) => return false,
+            Err(error) => {
+                warn!("{}", error);
+                return false;
+            }
+        };
+        match self.property {
+            ExifProperty::Any => true,
+            ExifProperty::Date => photo.taken().is_some(),
+            ExifProperty::Camera => photo.camera().is_some(),
+            ExifProperty::Gps => photo.has_gps(),
+        }
+    }
+}
+
+impl fmt::Debug for ExifRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.property {
+            ExifProperty::Any => write!(f, "exif"),
+            ExifProperty::Date => write!(f, "exif:date"),
+            ExifProperty::Camera => write!(f, "exif:camera"),
+            ExifProperty::Gps => write!(f, "exif:gps"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use testutils::*;
+
+    #[test]
+    fn photo_metadata_reads_exif() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                File::create(test_file).unwrap().write_all(&tiff_with_exif()).unwrap();
+                let photo = PhotoMetadata::read(test_file).unwrap().unwrap();
+                assert_eq!(photo.taken(), Some(NaiveDate::from_ymd_opt(2023, 5, 6).unwrap().and_hms_opt(7, 8, 9).unwrap()));
+                assert_eq!(photo.make(), Some("Canon".to_string()));
+                assert_eq!(photo.camera(), Some("Canon EOS R6".to_string()));
+                assert!(photo.has_gps());
+            })
+        });
+    }
+
+    #[test]
+    fn photo_metadata_without_exif() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                File::create(test_file).unwrap().write_all(b"\xFF\xD8\xFF\xD9").unwrap();
+                assert!(PhotoMetadata::read(test_file).unwrap().is_none());
+            })
+        });
+    }
+
+    #[test]
+    fn exif_rule_matches_properties() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let file_context = dummy_map_file_context();
+                let any = ExifRule::parse(None, "").unwrap();
+                let gps = ExifRule::parse(Some(Operator::Colon), "gps").unwrap();
+                assert!(!any.file_matches_rule(test_file, &file_context));
+                File::create(test_file).unwrap().write_all(&tiff_with_exif()).unwrap();
+                assert!(any.file_matches_rule(test_file, &file_context));
+                assert!(gps.file_matches_rule(test_file, &file_context));
+            })
+        });
+    }
+
+    #[test]
+    fn exif_rule_unknown_property() {
+        assert!(ExifRule::parse(Some(Operator::Colon), "lens").is_err());
+        assert!(ExifRule::parse(Some(Operator::Greater), "date").is_err());
+    }
+}
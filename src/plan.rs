@@ -0,0 +1,201 @@
+use chrono::{DateTime, Utc};
+use serde_json;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use action::{action_task, MapFileTask, TaskDescription};
+use context::{stays_inside, HashAlgorithm};
+use error::*;
+use hash::{has_tagged_hash, tagged_hash};
+use summary::files;
+
+/// Tasks written by `map plan` to be run later by `map apply`, with the state of each source when it was planned
+/// so that a plan is never applied to files that have changed since it was reviewed.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Plan {
+    /// The absolute directory the destinations of the tasks are relative to.
+    pub dest_dir: PathBuf,
+    /// The tasks in the order they were determined, which is the reverse of the order they're run in.
+    pub tasks: Vec<PlannedTask>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlannedTask {
+    #[serde(flatten)]
+    pub task: TaskDescription,
+    pub size: u64,
+    /// When the source was last modified, in RFC 3339 format.
+    pub modified: String,
+    /// The hash of the source's contents, prefixed with the algorithm, as in `blake3:...`.
+    pub hash: String,
+}
+
+impl Plan {
+    /// Plans `tasks`, which must all know what they'll do, recording the current state of their sources.
+    pub fn new(tasks: &[MapFileTask], dest_dir: &Path, algorithm: HashAlgorithm) -> Result<Plan> {
+        let mut planned_tasks = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let description = task.description().chain_err(|| "Unable to plan a task whose destination isn't known")?;
+            let source = fs::canonicalize(&description.source)
+                .chain_err(|| format!("Unable to find the absolute path of {}", description.source.to_string_lossy()))?;
+            let metadata = fs::metadata(&source)
+                .chain_err(|| format!("Unable to read metadata of {}", source.to_string_lossy()))?;
+            let hash = tagged_hash(&source, algorithm)
+                .chain_err(|| format!("Unable to hash {}", source.to_string_lossy()))?;
+            planned_tasks.push(PlannedTask {
+                task: TaskDescription { source: source.clone(), ..description.clone() },
+                size: metadata.len(),
+                modified: modified(&metadata, &source)?,
+                hash,
+            });
+        }
+        let dest_dir = if dest_dir.is_dir() {
+            fs::canonicalize(dest_dir)
+        } else {
+            ::std::env::current_dir().map(|directory| directory.join(dest_dir))
+        };
+        Ok(Plan {
+            dest_dir: dest_dir.chain_err(|| "Unable to find the absolute path of the destination directory")?,
+            tasks: planned_tasks,
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Plan> {
+        let file = File::open(path).chain_err(|| format!("Unable to open plan {}", path.to_string_lossy()))?;
+        serde_json::from_reader(file).chain_err(|| format!("Plan {} is invalid", path.to_string_lossy()))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).chain_err(|| format!("Unable to create plan {}", path.to_string_lossy()))?;
+        serde_json::to_writer_pretty(file, self).chain_err(|| format!("Unable to write plan {}", path.to_string_lossy()))
+    }
+
+    /// Fails, naming them, if any of the sources have changed since they were planned.
+    pub fn check_sources(&self) -> Result<()> {
+        let mut changed = vec![];
+        for planned_task in &self.tasks {
+            if source_changed(planned_task)? {
+                warn!("{} has changed since it was planned", planned_task.task.source.to_string_lossy());
+                changed.push(planned_task.task.source.to_string_lossy().into_owned());
+            }
+        }
+        if !changed.is_empty() {
+            bail!("Refusing to apply the plan, as {} changed since it was planned: {}", files(changed.len()),
+                  changed.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Fails, naming them, if any of the destinations are outside of the destination directory, as a plan may have
+    /// been edited since it was written.
+    pub fn check_destinations(&self) -> Result<()> {
+        let outside: Vec<String> = self.tasks.iter()
+            .map(|planned_task| &planned_task.task.destination)
+            .filter(|destination| !stays_inside(destination))
+            .map(|destination| destination.to_string_lossy().into_owned())
+            .collect();
+        if !outside.is_empty() {
+            bail!("Refusing to apply the plan, as {} would be mapped outside of {}: {}", files(outside.len()),
+                  self.dest_dir.to_string_lossy(), outside.join(", "));
+        }
+        Ok(())
+    }
+
+    /// The tasks that were planned, in the order they were determined.
+    pub fn into_tasks<'a>(self) -> Vec<MapFileTask<'a>> {
+        self.tasks.into_iter()
+            .map(|planned_task| {
+                let TaskDescription { action, source, destination, rule } = planned_task.task;
+                let relative_destination = destination.parent().map(Path::to_path_buf).unwrap_or_default();
+                let file_name = destination.file_name().map(|name| name.to_os_string());
+                action_task(action, source, relative_destination, file_name).with_rule(rule)
+            })
+            .collect()
+    }
+}
+
+fn source_changed(planned_task: &PlannedTask) -> Result<bool> {
+    let source = &planned_task.task.source;
+    let metadata = match fs::metadata(source) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(true),
+    };
+    if metadata.len() != planned_task.size || modified(&metadata, source)? != planned_task.modified {
+        return Ok(true);
+    }
+    let unchanged = has_tagged_hash(source, &planned_task.hash)
+        .chain_err(|| format!("Unable to hash {}", source.to_string_lossy()))?;
+    Ok(!unchanged)
+}
+
+fn modified(metadata: &fs::Metadata, file: &Path) -> Result<String> {
+    let modified = metadata.modified()
+        .chain_err(|| format!("Unable to read the modification time of {}", file.to_string_lossy()))?;
+    Ok(DateTime::<Utc>::from(modified).to_rfc3339())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use action::ActionKind;
+    use context::MapFileContext;
+    use journal::Journal;
+    use testutils::*;
+
+    #[test]
+    fn plan_round_trip_applies_tasks() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let output = test_directory.join("output");
+                let plan_file = test_directory.join("plan.json");
+                let tasks = vec![action_task(ActionKind::Copy, test_file.clone(), PathBuf::from("copies"), None)
+                    .with_rule("/test/".to_string())];
+                Plan::new(&tasks, &output, HashAlgorithm::Blake3).unwrap().write(&plan_file).unwrap();
+
+                let plan = Plan::read(&plan_file).unwrap();
+                assert_eq!(plan.dest_dir, fs::canonicalize(test_directory).unwrap().join("output"));
+                assert_eq!(plan.tasks[0].task.rule, "/test/");
+                plan.check_sources().unwrap();
+                let file_context = MapFileContext { dest_dir: plan.dest_dir.clone(), ..Default::default() };
+                for task in plan.into_tasks() {
+                    task.execute(&file_context, &mut Journal::default()).unwrap();
+                }
+                assert!(output.join("copies").join(test_file.file_name().unwrap()).is_file());
+            })
+        });
+    }
+
+    #[test]
+    fn plan_check_sources_refuses_changed_files() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::write(test_file, "contents").unwrap();
+                let tasks = vec![action_task(ActionKind::Move, test_file.clone(), PathBuf::from("moved"), None)];
+                let plan = Plan::new(&tasks, test_directory, HashAlgorithm::Sha256).unwrap();
+                plan.check_sources().unwrap();
+                let modified = fs::metadata(test_file).unwrap().modified().unwrap();
+                // Same size and modification time, different contents
+                fs::write(test_file, "contentz").unwrap();
+                File::options().write(true).open(test_file).unwrap().set_modified(modified).unwrap();
+                assert!(plan.check_sources().is_err());
+                fs::remove_file(test_file).unwrap();
+                assert!(plan.check_sources().is_err());
+            })
+        });
+    }
+
+    #[test]
+    fn plan_check_destinations_refuses_paths_outside_dest_dir() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let tasks = vec![action_task(ActionKind::Copy, test_file.clone(), PathBuf::from("copies"), None)];
+                let mut plan = Plan::new(&tasks, test_directory, HashAlgorithm::Blake3).unwrap();
+                plan.check_destinations().unwrap();
+                for destination in &["../escaped.txt", "copies/../../escaped.txt", "/tmp/escaped.txt"] {
+                    plan.tasks[0].task.destination = PathBuf::from(destination);
+                    assert!(plan.check_destinations().is_err());
+                }
+            })
+        });
+    }
+}
@@ -0,0 +1,192 @@
+use std::fs::{self, File, FileTimes};
+use std::path::Path;
+
+use context::Preserve;
+use error::*;
+
+/// Gives the copy `destination` the attributes of `file` chosen by `--preserve`. Ownership, extended attributes
+/// and ACLs depend on privileges and on what the destination's filesystem supports, so failing to preserve them
+/// is only a warning.
+pub fn preserve_attributes(file: &Path, destination: &Path, preserve: Preserve) -> Result<()> {
+    let metadata = fs::metadata(file).chain_err(|| format!("Unable to read metadata of {}", file.to_string_lossy()))?;
+    if preserve.owner {
+        if let Err(error) = copy_owner(&metadata, destination) {
+            warn!("Unable to preserve the owner of {}: {}", file.to_string_lossy(), error);
+        }
+    }
+    if preserve.xattr || preserve.acl {
+        if let Err(error) = copy_extended_attributes(file, destination, preserve) {
+            warn!("Unable to preserve the extended attributes of {}: {}", file.to_string_lossy(), error);
+        }
+    }
+    // After the owner, as changing it clears the setuid and setgid bits
+    if preserve.mode {
+        fs::set_permissions(destination, metadata.permissions())
+            .chain_err(|| format!("Unable to preserve the mode of {}", file.to_string_lossy()))?;
+    }
+    // Last, as nothing else may change them afterwards
+    if preserve.times {
+        let mut times = FileTimes::new();
+        if let Ok(accessed) = metadata.accessed() {
+            times = times.set_accessed(accessed);
+        }
+        if let Ok(modified) = metadata.modified() {
+            times = times.set_modified(modified);
+        }
+        File::open(destination)
+            .and_then(|copy| copy.set_times(times))
+            .chain_err(|| format!("Unable to preserve the times of {}", file.to_string_lossy()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_owner(metadata: &fs::Metadata, destination: &Path) -> ::std::io::Result<()> {
+    use std::os::unix::fs::{chown, MetadataExt};
+
+    chown(destination, Some(metadata.uid()), Some(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_metadata: &fs::Metadata, _destination: &Path) -> ::std::io::Result<()> {
+    Err(::std::io::Error::new(::std::io::ErrorKind::Unsupported, "Owners are only supported on Unix"))
+}
+
+/// POSIX ACLs are stored as these extended attributes on Linux.
+const ACL_ATTRIBUTES: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+/// Copies the extended attributes of `file`, which are ACLs or not as `preserve` says.
+#[cfg(target_os = "linux")]
+fn copy_extended_attributes(file: &Path, destination: &Path, preserve: Preserve) -> ::std::io::Result<()> {
+    let source = File::open(file)?;
+    let target = File::open(destination)?;
+    for name in xattr::names(&source)? {
+        let is_acl = ACL_ATTRIBUTES.iter().any(|acl| acl.as_bytes() == name.as_bytes());
+        if (is_acl && preserve.acl) || (!is_acl && preserve.xattr) {
+            let value = xattr::get(&source, &name)?;
+            xattr::set(&target, &name, &value)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_extended_attributes(_file: &Path, _destination: &Path, _preserve: Preserve) -> ::std::io::Result<()> {
+    Err(::std::io::Error::new(::std::io::ErrorKind::Unsupported, "Extended attributes are only supported on Linux"))
+}
+
+#[cfg(target_os = "linux")]
+mod xattr {
+    use libc;
+    use std::ffi::{CStr, CString};
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    pub fn names(file: &File) -> io::Result<Vec<CString>> {
+        let buffer = read_sized(|buffer, size| unsafe {
+            libc::flistxattr(file.as_raw_fd(), buffer as *mut libc::c_char, size)
+        })?;
+        Ok(buffer
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+            .filter_map(|name| CString::new(name).ok())
+            .collect())
+    }
+
+    pub fn get(file: &File, name: &CStr) -> io::Result<Vec<u8>> {
+        read_sized(|buffer, size| unsafe {
+            libc::fgetxattr(file.as_raw_fd(), name.as_ptr(), buffer as *mut libc::c_void, size)
+        })
+    }
+
+    pub fn set(file: &File, name: &CStr, value: &[u8]) -> io::Result<()> {
+        let result = unsafe {
+            libc::fsetxattr(file.as_raw_fd(), name.as_ptr(), value.as_ptr() as *const libc::c_void, value.len(), 0)
+        };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Calls `read` once to find the size of the value, then again to read it.
+    fn read_sized(read: impl Fn(*mut u8, usize) -> libc::ssize_t) -> io::Result<Vec<u8>> {
+        let size = read(ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buffer = vec![0u8; size as usize];
+        let read_size = read(buffer.as_mut_ptr(), buffer.len());
+        if read_size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buffer.truncate(read_size as usize);
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use testutils::*;
+
+    #[test]
+    fn preserve_attributes_times() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let modified = SystemTime::now() - Duration::from_secs(86400);
+                File::options().write(true).open(test_file).unwrap().set_modified(modified).unwrap();
+                let destination = test_directory.join(random_string() + "_copy");
+                fs::write(&destination, "").unwrap();
+                preserve_attributes(test_file, &destination, Preserve::default()).unwrap();
+                assert_ne!(fs::metadata(&destination).unwrap().modified().unwrap(), modified);
+                let preserve = Preserve { times: true, ..Preserve::default() };
+                preserve_attributes(test_file, &destination, preserve).unwrap();
+                assert_eq!(fs::metadata(&destination).unwrap().modified().unwrap(), modified);
+            })
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_attributes_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                fs::set_permissions(test_file, fs::Permissions::from_mode(0o2750)).unwrap();
+                let destination = test_directory.join(random_string() + "_copy");
+                fs::write(&destination, "").unwrap();
+                let preserve = Preserve { mode: true, ..Preserve::default() };
+                preserve_attributes(test_file, &destination, preserve).unwrap();
+                assert_eq!(fs::metadata(&destination).unwrap().permissions().mode() & 0o7777, 0o2750);
+            })
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preserve_attributes_xattr() {
+        use std::ffi::CString;
+
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let name = CString::new("user.map.test").unwrap();
+                // Not every filesystem supports user extended attributes
+                if xattr::set(&File::open(test_file).unwrap(), &name, b"value").is_err() {
+                    return;
+                }
+                let destination = test_directory.join(random_string() + "_copy");
+                fs::write(&destination, "").unwrap();
+                let preserve = Preserve { xattr: true, ..Preserve::default() };
+                preserve_attributes(test_file, &destination, preserve).unwrap();
+                assert_eq!(xattr::get(&File::open(&destination).unwrap(), &name).unwrap(), b"value");
+            })
+        });
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path};
+
+use action::{ActionKind, MapFileTask, TaskDescription};
+use context::{relative_path, MapFileContext};
+
+/// How `--report` shows what a dry run would do.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    /// The destination directory as it would look afterwards.
+    Tree,
+    /// A row for each file with the rule that matched it, in the order the files are mapped.
+    Table,
+}
+
+/// Renders what `tasks` would do, with ANSI colours if `styled`. Tasks whose destination can't be known until they
+/// run are left out.
+pub fn render(format: ReportFormat, tasks: &[MapFileTask], file_context: &MapFileContext, styled: bool) -> String {
+    let descriptions: Vec<&TaskDescription> = tasks.iter().filter_map(MapFileTask::description).collect();
+    match format {
+        ReportFormat::Tree => render_tree(&descriptions, file_context, styled),
+        ReportFormat::Table => render_table(&descriptions, file_context),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mark {
+    Existing,
+    New,
+    /// More than one file would be mapped here, counting the file already there.
+    Conflict,
+    /// The file would be moved away.
+    Removed,
+}
+
+#[derive(Debug)]
+struct Node {
+    mark: Mark,
+    directory: bool,
+    /// The files mapped to this node, relative to the source directory.
+    sources: Vec<String>,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn new(mark: Mark, directory: bool) -> Node {
+        Node { mark, directory, sources: vec![], children: BTreeMap::new() }
+    }
+
+    /// The node at `path` below this one, adding it and its missing ancestors with `mark`.
+    fn insert(&mut self, path: &Path, mark: Mark, directory: bool) -> &mut Node {
+        let names: Vec<String> = path.components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let mut node = self;
+        for (index, name) in names.iter().enumerate() {
+            let last = index + 1 == names.len();
+            node = node.children.entry(name.clone())
+                .or_insert_with(|| Node::new(mark, !last || directory));
+        }
+        node
+    }
+
+    /// Adds what's already in `directory` under this node, without reading subdirectories.
+    fn add_existing(&mut self, directory: &Path) {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let is_directory = entry.file_type().map(|file_type| file_type.is_dir()).unwrap_or(false);
+            self.children.entry(entry.file_name().to_string_lossy().into_owned())
+                .or_insert_with(|| Node::new(Mark::Existing, is_directory));
+        }
+    }
+}
+
+fn render_tree(descriptions: &[&TaskDescription], file_context: &MapFileContext, styled: bool) -> String {
+    let mut destination = Node::new(Mark::Existing, true);
+    let mut moved_away = Node::new(Mark::Existing, true);
+    destination.add_existing(&file_context.dest_dir);
+    for description in descriptions {
+        // Show what's already in each directory a file is mapped into, so conflicts can be seen in context
+        let mut directory = &mut destination;
+        let mut existing = file_context.dest_dir.clone();
+        for component in description.destination.parent().into_iter().flat_map(Path::components) {
+            existing.push(component);
+            directory = directory.insert(Path::new(component.as_os_str()), Mark::New, true);
+            if directory.mark == Mark::Existing && directory.children.is_empty() {
+                directory.add_existing(&existing);
+            }
+        }
+        let file_name = description.destination.file_name().map(Path::new).unwrap_or_else(|| Path::new(""));
+        let file = directory.insert(file_name, Mark::New, false);
+        if file.mark == Mark::Existing || !file.sources.is_empty() {
+            file.mark = Mark::Conflict;
+        }
+        file.sources.push(relative_path(&file_context.source_dir, &description.source));
+
+        if description.action == ActionKind::Move {
+            let source = relative_path(&file_context.source_dir, &description.source);
+            moved_away.insert(Path::new(&source), Mark::Existing, false).mark = Mark::Removed;
+        }
+    }
+
+    let mut lines = vec![style(&format!("{}/", file_context.dest_dir.to_string_lossy()), Mark::Existing, styled)];
+    render_children(&destination, "", styled, &mut lines);
+    if !moved_away.children.is_empty() {
+        lines.push(String::new());
+        lines.push(style(&format!("{}/", file_context.source_dir.to_string_lossy()), Mark::Existing, styled));
+        render_children(&moved_away, "", styled, &mut lines);
+    }
+    lines.push(String::new());
+    lines.push("+ new, ! conflict, - moved away".to_string());
+    lines.join("\n") + "\n"
+}
+
+fn render_children(node: &Node, indent: &str, styled: bool, lines: &mut Vec<String>) {
+    let count = node.children.len();
+    for (index, (name, child)) in node.children.iter().enumerate() {
+        let last = index + 1 == count;
+        let marker = match child.mark {
+            Mark::Existing => ' ',
+            Mark::New => '+',
+            Mark::Conflict => '!',
+            Mark::Removed => '-',
+        };
+        let mut label = format!("{} {}{}", marker, name, if child.directory { "/" } else { "" });
+        if !child.sources.is_empty() {
+            label += &format!(" <- {}", child.sources.join(", "));
+        }
+        lines.push(format!("{}{}{}", indent, if last { "└── " } else { "├── " }, style(&label, child.mark, styled)));
+        render_children(child, &format!("{}{}", indent, if last { "    " } else { "│   " }), styled, lines);
+    }
+}
+
+fn style(text: &str, mark: Mark, styled: bool) -> String {
+    let code = match mark {
+        _ if !styled => return text.to_string(),
+        Mark::Existing => return text.to_string(),
+        Mark::New => "32",
+        Mark::Conflict => "1;31",
+        Mark::Removed => "9",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+fn render_table(descriptions: &[&TaskDescription], file_context: &MapFileContext) -> String {
+    let mut rows = vec![["File".to_string(), "Rule".to_string(), "Action".to_string(), "Destination".to_string()]];
+    // Tasks are run from the last, so this lists files in the order they're mapped
+    for description in descriptions.iter().rev() {
+        rows.push([
+            relative_path(&file_context.source_dir, &description.source),
+            description.rule.clone(),
+            action_name(description.action).to_string(),
+            relative_path(Path::new(""), &description.destination),
+        ]);
+    }
+    let mut widths = [0; 4];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in &rows {
+        let cells: Vec<String> = row.iter().zip(widths.iter())
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect();
+        table += cells.join("  ").trim_end();
+        table += "\n";
+    }
+    table
+}
+
+fn action_name(action: ActionKind) -> &'static str {
+    match action {
+        ActionKind::Copy => "copy",
+        ActionKind::Move => "move",
+        ActionKind::Symlink => "symlink",
+        ActionKind::RelativeSymlink => "relative symlink",
+        ActionKind::Hardlink => "hardlink",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use action::action_task;
+    use std::path::PathBuf;
+    use testutils::*;
+
+    #[test]
+    fn render_tree_marks_new_conflicting_and_moved_files() {
+        with_default_test_directory(|test_directory| {
+            let source_dir = test_directory.join("source");
+            let dest_dir = test_directory.join("destination");
+            fs::create_dir_all(dest_dir.join("photos")).unwrap();
+            fs::write(dest_dir.join("photos").join("a.jpg"), "existing").unwrap();
+            fs::write(dest_dir.join("notes.txt"), "existing").unwrap();
+            let tasks = vec![
+                action_task(ActionKind::Move, source_dir.join("a.jpg"), PathBuf::from("photos"), None),
+                action_task(ActionKind::Copy, source_dir.join("b.jpg"), PathBuf::from("photos/2018"), None),
+                action_task(ActionKind::Copy, source_dir.join("c.txt"), PathBuf::from("photos/2018"),
+                            Some("b.jpg".into())),
+            ];
+            let file_context = MapFileContext { source_dir: source_dir.clone(), dest_dir: dest_dir.clone(),
+                                                ..Default::default() };
+
+            let expected = format!("{}/
+├──   notes.txt
+└──   photos/
+    ├── + 2018/
+    │   └── ! b.jpg <- b.jpg, c.txt
+    └── ! a.jpg <- a.jpg
+
+{}/
+└── - a.jpg
+
++ new, ! conflict, - moved away
+", dest_dir.to_string_lossy(), source_dir.to_string_lossy());
+            assert_eq!(render(ReportFormat::Tree, &tasks, &file_context, false), expected);
+        });
+    }
+
+    #[test]
+    fn render_table_lists_each_file() {
+        let file_context = dummy_map_file_context();
+        let tasks = vec![
+            action_task(ActionKind::Copy, file_context.source_dir.join("lime.txt"), PathBuf::from("Lime Files"), None)
+                .with_rule("/lime/".to_string()),
+            action_task(ActionKind::RelativeSymlink, file_context.source_dir.join("a.txt"), PathBuf::from(""), None)
+                .with_rule("/a/".to_string()),
+        ];
+
+        assert_eq!(render(ReportFormat::Table, &tasks, &file_context, false), "\
+File      Rule    Action            Destination
+a.txt     /a/     relative symlink  a.txt
+lime.txt  /lime/  copy              Lime Files/lime.txt
+");
+    }
+}
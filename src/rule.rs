@@ -3,12 +3,20 @@ use std::path::PathBuf;
 use regex::Regex;
 
 use context::MapFileContext;
+use error::*;
+use glob::Glob;
+use metadata::{format_operator, Operator};
+use template::TemplateVariables;
 
 pub trait MapRule: fmt::Debug {
     fn file_matches_rule(&self, file: &PathBuf, file_context: &MapFileContext) -> bool;
+
+    /// Template variables made available to the destination of a file that matches this rule.
+    fn captures(&self, _file: &PathBuf, _file_context: &MapFileContext) -> TemplateVariables {
+        TemplateVariables::new()
+    }
 }
 
-#[derive(Debug)]
 pub struct RegexRule {
     rule: Regex,
 }
@@ -20,15 +28,239 @@ impl RegexRule {
 }
 
 impl MapRule for RegexRule {
+    fn file_matches_rule(&self, file: &PathBuf, file_context: &MapFileContext) -> bool {
+        self.rule.is_match(&file_context.match_text(file))
+    }
+
+    fn captures(&self, file: &PathBuf, file_context: &MapFileContext) -> TemplateVariables {
+        regex_captures(&self.rule, &file_context.match_text(file))
+    }
+}
+
+impl fmt::Debug for RegexRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}/", escape_slashes(&self.rule))
+    }
+}
+
+/// Matches a regex against the path of a file relative to the source directory, regardless of what
+/// rules are otherwise matched against.
+pub struct RelativePathRegexRule {
+    rule: Regex,
+}
+
+impl RelativePathRegexRule {
+    pub fn new(regex: Regex) -> RelativePathRegexRule {
+        RelativePathRegexRule { rule: regex }
+    }
+}
+
+impl MapRule for RelativePathRegexRule {
+    fn file_matches_rule(&self, file: &PathBuf, file_context: &MapFileContext) -> bool {
+        self.rule.is_match(&file_context.relative_path(file))
+    }
+
+    fn captures(&self, file: &PathBuf, file_context: &MapFileContext) -> TemplateVariables {
+        regex_captures(&self.rule, &file_context.relative_path(file))
+    }
+}
+
+impl fmt::Debug for RelativePathRegexRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "p/{}/", escape_slashes(&self.rule))
+    }
+}
+
+/// Matches a shell-style glob. Globs containing a `/` are matched against the path of a file relative
+/// to the source directory, others against the same text as regex rules.
+pub struct GlobRule {
+    rule: Glob,
+}
+
+impl GlobRule {
+    pub fn new(glob: Glob) -> GlobRule {
+        GlobRule { rule: glob }
+    }
+}
+
+impl MapRule for GlobRule {
+    fn file_matches_rule(&self, file: &PathBuf, file_context: &MapFileContext) -> bool {
+        if self.rule.has_path_separator() {
+            self.rule.is_match(&file_context.relative_path(file))
+        } else {
+            self.rule.is_match(&file_context.match_text(file))
+        }
+    }
+}
+
+impl fmt::Debug for GlobRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "g\"{}\"", self.rule)
+    }
+}
+
+/// Matches files by their extension, ignoring case, e.g. `ext:mp3|flac`.
+pub struct ExtensionRule {
+    extensions: Vec<String>,
+}
+
+impl ExtensionRule {
+    pub fn parse(operator: Option<Operator>, value: &str) -> Result<ExtensionRule> {
+        if operator != Some(Operator::Colon) {
+            bail!("Expected ext:<extension>, found ext{}", format_operator(operator));
+        }
+        let extensions: Vec<String> = value
+            .split('|')
+            .map(|extension| extension.trim_start_matches('.').to_lowercase())
+            .collect();
+        if extensions.iter().any(String::is_empty) {
+            bail!("Empty extension in ext:{}", value);
+        }
+
+        Ok(ExtensionRule { extensions })
+    }
+}
+
+impl MapRule for ExtensionRule {
     fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
-        let file_name = file.file_name().unwrap();
-        self.rule.is_match(&file_name.to_string_lossy())
+        match file.extension() {
+            Some(extension) => self.extensions.contains(&extension.to_string_lossy().to_lowercase()),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Debug for ExtensionRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ext:{}", self.extensions.join("|"))
     }
 }
 
+/// Matches files that match all of its rules.
+pub struct AllRule {
+    rules: Vec<Box<dyn MapRule>>,
+}
+
+impl AllRule {
+    pub fn new(rules: Vec<Box<dyn MapRule>>) -> AllRule {
+        AllRule { rules }
+    }
+}
+
+impl MapRule for AllRule {
+    fn file_matches_rule(&self, file: &PathBuf, file_context: &MapFileContext) -> bool {
+        self.rules.iter().all(|rule| rule.file_matches_rule(file, file_context))
+    }
+
+    /// The captures of all of the rules, where earlier rules take precedence.
+    fn captures(&self, file: &PathBuf, file_context: &MapFileContext) -> TemplateVariables {
+        let mut variables = TemplateVariables::new();
+        for rule in self.rules.iter().rev() {
+            variables.extend(rule.captures(file, file_context));
+        }
+
+        variables
+    }
+}
+
+impl fmt::Debug for AllRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_rule_list(f, &self.rules, " & ")
+    }
+}
+
+/// Matches files that match any of its rules.
+pub struct AnyRule {
+    rules: Vec<Box<dyn MapRule>>,
+}
+
+impl AnyRule {
+    pub fn new(rules: Vec<Box<dyn MapRule>>) -> AnyRule {
+        AnyRule { rules }
+    }
+}
+
+impl MapRule for AnyRule {
+    fn file_matches_rule(&self, file: &PathBuf, file_context: &MapFileContext) -> bool {
+        self.rules.iter().any(|rule| rule.file_matches_rule(file, file_context))
+    }
+
+    /// The captures of the first rule that matches.
+    fn captures(&self, file: &PathBuf, file_context: &MapFileContext) -> TemplateVariables {
+        self.rules
+            .iter()
+            .find(|rule| rule.file_matches_rule(file, file_context))
+            .map(|rule| rule.captures(file, file_context))
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for AnyRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_rule_list(f, &self.rules, " | ")
+    }
+}
+
+/// Matches files that don't match its rule.
+pub struct NotRule {
+    rule: Box<dyn MapRule>,
+}
+
+impl NotRule {
+    pub fn new(rule: Box<dyn MapRule>) -> NotRule {
+        NotRule { rule }
+    }
+}
+
+impl MapRule for NotRule {
+    fn file_matches_rule(&self, file: &PathBuf, file_context: &MapFileContext) -> bool {
+        !self.rule.file_matches_rule(file, file_context)
+    }
+}
+
+impl fmt::Debug for NotRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "!{:?}", self.rule)
+    }
+}
+
+fn write_rule_list(f: &mut fmt::Formatter, rules: &[Box<dyn MapRule>], separator: &str) -> fmt::Result {
+    write!(f, "(")?;
+    for (index, rule) in rules.iter().enumerate() {
+        if index > 0 {
+            write!(f, "{}", separator)?;
+        }
+        write!(f, "{:?}", rule)?;
+    }
+    write!(f, ")")
+}
+
+/// Writes `regex` as it would appear between `/` delimiters in a rule.
+fn escape_slashes(regex: &Regex) -> String {
+    regex.as_str().replace('/', "\\/")
+}
+
+/// Collects the numbered and named capture groups of `regex` against `text`. Groups that did not
+/// participate in the match are captured as empty strings.
+fn regex_captures(regex: &Regex, text: &str) -> TemplateVariables {
+    let mut variables = TemplateVariables::new();
+    if let Some(captures) = regex.captures(text) {
+        for (index, name) in regex.capture_names().enumerate() {
+            let value = captures.get(index).map_or("", |capture| capture.as_str()).to_string();
+            if let Some(name) = name {
+                variables.insert(name.to_string(), value.clone());
+            }
+            variables.insert(index.to_string(), value);
+        }
+    }
+
+    variables
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use context::MatchTarget;
 
     #[test]
     fn regex_rule_with_matching_file() {
@@ -46,11 +278,120 @@ mod test {
         assert_eq!(is_match, false);
     }
 
+    #[test]
+    fn regex_rule_captures_groups() {
+        let rule = RegexRule { rule: Regex::new(r"^(\w+)_(?P<year>\d{4})").unwrap() };
+        let file = PathBuf::from("./reports/sales_2019.pdf");
+        let captures = rule.captures(&file, &dummy_map_file_context());
+        assert_eq!(captures.get("0").unwrap(), "sales_2019");
+        assert_eq!(captures.get("1").unwrap(), "sales");
+        assert_eq!(captures.get("2").unwrap(), "2019");
+        assert_eq!(captures.get("year").unwrap(), "2019");
+    }
+
+    #[test]
+    fn regex_rule_captures_unmatched_group_as_empty() {
+        let rule = RegexRule { rule: Regex::new(r"^(a)?(b)").unwrap() };
+        let captures = rule.captures(&PathBuf::from("b.txt"), &dummy_map_file_context());
+        assert_eq!(captures.get("1").unwrap(), "");
+        assert_eq!(captures.get("2").unwrap(), "b");
+    }
+
+    #[test]
+    fn regex_rule_matches_relative_path() {
+        let rule = RegexRule { rule: Regex::new("^nested/(.+)$").unwrap() };
+        let file_context = MapFileContext {
+            match_target: MatchTarget::RelativePath,
+            ..dummy_map_file_context()
+        };
+        let file = PathBuf::from("dummy-source-dir/nested/match.txt");
+        assert!(rule.file_matches_rule(&file, &file_context));
+        assert_eq!(rule.captures(&file, &file_context).get("1").unwrap(), "match.txt");
+    }
+
+    #[test]
+    fn relative_path_regex_rule_matches_parents() {
+        let rule = RelativePathRegexRule { rule: Regex::new(r"^(\d{4})/(Q\d)/").unwrap() };
+        let file = PathBuf::from("dummy-source-dir/2019/Q3/report.pdf");
+        assert!(rule.file_matches_rule(&file, &dummy_map_file_context()));
+        let captures = rule.captures(&file, &dummy_map_file_context());
+        assert_eq!(captures.get("1").unwrap(), "2019");
+        assert_eq!(captures.get("2").unwrap(), "Q3");
+    }
+
+    #[test]
+    fn relative_path_regex_rule_ignores_source_dir() {
+        let rule = RelativePathRegexRule { rule: Regex::new("dummy").unwrap() };
+        let file = PathBuf::from("dummy-source-dir/2019/report.pdf");
+        assert!(!rule.file_matches_rule(&file, &dummy_map_file_context()));
+    }
+
+    #[test]
+    fn glob_rule_matches_file_name() {
+        let rule = GlobRule::new(Glob::new("*.epub").unwrap());
+        let file_context = dummy_map_file_context();
+        assert!(rule.file_matches_rule(&PathBuf::from("dummy-source-dir/nested/book.epub"), &file_context));
+        assert!(!rule.file_matches_rule(&PathBuf::from("dummy-source-dir/book.pdf"), &file_context));
+    }
+
+    #[test]
+    fn glob_rule_with_separator_matches_relative_path() {
+        let rule = GlobRule::new(Glob::new("**/bundle/*.epub").unwrap());
+        let file_context = dummy_map_file_context();
+        assert!(rule.file_matches_rule(&PathBuf::from("dummy-source-dir/a/bundle/book.epub"), &file_context));
+        assert!(!rule.file_matches_rule(&PathBuf::from("dummy-source-dir/a/book.epub"), &file_context));
+    }
+
+    #[test]
+    fn all_rule_requires_every_rule() {
+        let rule = AllRule::new(vec![
+            Box::new(RegexRule::new(Regex::new("design").unwrap())),
+            Box::new(NotRule::new(Box::new(RegexRule::new(Regex::new(r"\.part$").unwrap())))),
+        ]);
+        let file_context = dummy_map_file_context();
+        assert!(rule.file_matches_rule(&PathBuf::from("design.pdf"), &file_context));
+        assert!(!rule.file_matches_rule(&PathBuf::from("design.pdf.part"), &file_context));
+        assert!(!rule.file_matches_rule(&PathBuf::from("other.pdf"), &file_context));
+    }
+
+    #[test]
+    fn all_rule_merges_captures() {
+        let rule = AllRule::new(vec![
+            Box::new(RegexRule::new(Regex::new("^(?P<first>[a-z]+)").unwrap())),
+            Box::new(RegexRule::new(Regex::new("(?P<last>[0-9]+)").unwrap())),
+        ]);
+        let captures = rule.captures(&PathBuf::from("design2019.pdf"), &dummy_map_file_context());
+        assert_eq!(captures.get("first").unwrap(), "design");
+        assert_eq!(captures.get("last").unwrap(), "2019");
+        assert_eq!(captures.get("0").unwrap(), "design");
+    }
+
+    #[test]
+    fn any_rule_captures_first_matching_rule() {
+        let rule = AnyRule::new(vec![
+            Box::new(RegexRule::new(Regex::new("^(nomatch)").unwrap())),
+            Box::new(RegexRule::new(Regex::new("^(design)").unwrap())),
+        ]);
+        let file = PathBuf::from("design.pdf");
+        assert!(rule.file_matches_rule(&file, &dummy_map_file_context()));
+        assert_eq!(rule.captures(&file, &dummy_map_file_context()).get("1").unwrap(), "design");
+    }
+
+    #[test]
+    fn rule_debug_output_is_readable() {
+        let rule = AnyRule::new(vec![
+            Box::new(NotRule::new(Box::new(RelativePathRegexRule::new(Regex::new("a/b").unwrap())))),
+            Box::new(GlobRule::new(Glob::new("*.pdf").unwrap())),
+        ]);
+        assert_eq!(format!("{:?}", rule), r#"(!p/a\/b/ | g"*.pdf")"#);
+    }
+
     fn dummy_map_file_context() -> MapFileContext {
         MapFileContext {
             source_dir: PathBuf::from("dummy-source-dir"),
             dest_dir: PathBuf::from("dummy-dest-dir"),
-            dry_run: false
+            dry_run: false,
+            ..Default::default()
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,141 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// What a task did with its file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The file was mapped to its destination.
+    Mapped,
+    /// The file was mapped, replacing the file that was already at its destination.
+    Overwritten,
+    /// The file was mapped to this path instead, as a file was already at its destination.
+    Renamed(PathBuf),
+    /// The file was left alone, as a file was already at its destination.
+    Skipped,
+    /// The file wasn't mapped, as this file at its destination has the same contents. Moved files are
+    /// deleted.
+    AlreadyMapped(PathBuf),
+}
+
+/// Counts of what was done with each file, reported once all the tasks have run.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    mapped: usize,
+    overwritten: usize,
+    renamed: usize,
+    skipped: usize,
+    already_mapped: usize,
+    verification_failures: usize,
+    /// Files of a group that weren't mapped, as it has a more preferred format.
+    not_preferred: usize,
+}
+
+impl Summary {
+    pub fn record(&mut self, outcome: &Outcome) {
+        match *outcome {
+            Outcome::Mapped => self.mapped += 1,
+            Outcome::Overwritten => {
+                self.mapped += 1;
+                self.overwritten += 1;
+            }
+            Outcome::Renamed(_) => {
+                self.mapped += 1;
+                self.renamed += 1;
+            }
+            Outcome::Skipped => self.skipped += 1,
+            Outcome::AlreadyMapped(_) => self.already_mapped += 1,
+        }
+    }
+
+    pub fn record_verification_failure(&mut self) {
+        self.verification_failures += 1;
+    }
+
+    pub fn record_not_preferred(&mut self) {
+        self.not_preferred += 1;
+    }
+
+    /// Logs the summary, as a warning if any files were left alone or failed so that it's seen without `-v`.
+    pub fn log(&self) {
+        if self.skipped > 0 || self.not_preferred > 0 || self.verification_failures > 0 {
+            warn!("{}", self);
+        } else {
+            info!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut mapped = format!("Mapped {}", files(self.mapped));
+        let mut details = vec![];
+        if self.overwritten > 0 {
+            details.push(format!("{} overwriting an existing file", self.overwritten));
+        }
+        if self.renamed > 0 {
+            details.push(format!("{} renamed", self.renamed));
+        }
+        if !details.is_empty() {
+            mapped += &format!(" ({})", details.join(", "));
+        }
+        let mut clauses = vec![mapped];
+        if self.already_mapped > 0 {
+            clauses.push(format!("found {} already mapped", files(self.already_mapped)));
+        }
+        if self.skipped > 0 {
+            clauses.push(format!("skipped {} whose destination already existed", files(self.skipped)));
+        }
+        if self.not_preferred > 0 {
+            clauses.push(format!("left {} in favour of a preferred format", files(self.not_preferred)));
+        }
+        if self.verification_failures > 0 {
+            clauses.push(format!("{} failed verification", files(self.verification_failures)));
+        }
+        let last = clauses.pop().unwrap();
+        if clauses.is_empty() {
+            write!(f, "{}", last)
+        } else {
+            write!(f, "{} and {}", clauses.join(", "), last)
+        }
+    }
+}
+
+pub fn files(count: usize) -> String {
+    if count == 1 {
+        "1 file".to_string()
+    } else {
+        format!("{} files", count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summary_counts_outcomes() {
+        let mut summary = Summary::default();
+        for outcome in &[Outcome::Mapped, Outcome::Overwritten, Outcome::Renamed(PathBuf::from("a (1).txt")),
+                         Outcome::Skipped, Outcome::Skipped, Outcome::AlreadyMapped(PathBuf::from("b.txt"))] {
+            summary.record(outcome);
+        }
+        assert_eq!(summary.to_string(),
+                   "Mapped 3 files (1 overwriting an existing file, 1 renamed), found 1 file already mapped \
+                    and skipped 2 files whose destination already existed");
+    }
+
+    #[test]
+    fn summary_counts_verification_failures() {
+        let mut summary = Summary::default();
+        summary.record(&Outcome::Mapped);
+        summary.record_verification_failure();
+        assert_eq!(summary.to_string(), "Mapped 1 file and 1 file failed verification");
+    }
+
+    #[test]
+    fn summary_without_conflicts() {
+        let mut summary = Summary::default();
+        summary.record(&Outcome::Mapped);
+        assert_eq!(summary.to_string(), "Mapped 1 file");
+    }
+}
@@ -0,0 +1,120 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use audio::{AudioTags, AUDIO_TAG_NAMES};
+use context::MapFileContext;
+use ebook::{EbookMetadata, EBOOK_FIELD_NAMES};
+use error::*;
+use metadata::{format_operator, Operator};
+use rule::MapRule;
+
+/// Names of the tags read from the content of ebooks and audio files. Each is a template variable and a
+/// predicate, e.g. `{author}` and `author:gaiman`.
+pub fn tag_names() -> Vec<&'static str> {
+    let mut names = EBOOK_FIELD_NAMES.to_vec();
+    names.extend(AUDIO_TAG_NAMES.iter().filter(|name| !EBOOK_FIELD_NAMES.contains(name)));
+    names
+}
+
+/// Reads the tag `name` of `file` from its ebook metadata or, failing that, its audio tags.
+pub fn read_tag(name: &str, file: &Path) -> Result<Option<String>> {
+    if EBOOK_FIELD_NAMES.contains(&name) {
+        if let Some(metadata) = EbookMetadata::read(file)? {
+            return Ok(metadata.get(name).map(str::to_string));
+        }
+    }
+    if AUDIO_TAG_NAMES.contains(&name) {
+        if let Some(tags) = AudioTags::read(file)? {
+            return Ok(tags.get(name).map(str::to_string));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Matches files whose tag contains the given text, ignoring case, e.g. `author:gaiman` or
+/// `series:"Discworld"`.
+pub struct TagRule {
+    name: &'static str,
+    text: String,
+}
+
+impl TagRule {
+    pub fn parse(name: &str, operator: Option<Operator>, value: &str) -> Result<TagRule> {
+        let name = match tag_names().into_iter().find(|&tag_name| tag_name == name) {
+            Some(name) => name,
+            None => bail!("Unknown tag {}. This is a bug.", name),
+        };
+        if operator != Some(Operator::Colon) {
+            bail!("Expected {}:<text>, found {}{}", name, name, format_operator(operator));
+        }
+
+        Ok(TagRule {
+            name,
+            text: value.to_string(),
+        })
+    }
+}
+
+impl MapRule for TagRule {
+    fn file_matches_rule(&self, file: &PathBuf, _file_context: &MapFileContext) -> bool {
+        match read_tag(self.name, file) {
+            Ok(Some(value)) => value.to_lowercase().contains(&self.text.to_lowercase()),
+            Ok(None) => false,
+            Err(error) => {
+                warn!("{}", error);
+                false
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TagRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.text.contains(|character: char| character.is_whitespace() || character == ')') {
+            write!(f, "{}:\"{}\"", self.name, self.text)
+        } else {
+            write!(f, "{}:{}", self.name, self.text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use testutils::*;
+
+    #[test]
+    fn read_tag_from_ebooks_and_audio() {
+        with_default_test_directory(|test_directory| {
+            let book = test_directory.join("book.epub");
+            File::create(&book).unwrap().write_all(&epub_with_metadata()).unwrap();
+            let song = test_directory.join("song.mp3");
+            File::create(&song).unwrap().write_all(&mp3_with_id3()).unwrap();
+            assert_eq!(read_tag("title", &book).unwrap(), Some("Evil by Design".to_string()));
+            assert_eq!(read_tag("title", &song).unwrap(), Some("Digital Love".to_string()));
+            assert_eq!(read_tag("author", &song).unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn tag_rule_matches_text_ignoring_case() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                File::create(test_file).unwrap().write_all(&pdf_with_info()).unwrap();
+                let file_context = dummy_map_file_context();
+                let author = TagRule::parse("author", Some(Operator::Colon), "nodder").unwrap();
+                let title = TagRule::parse("title", Some(Operator::Colon), "Good by Design").unwrap();
+                assert!(author.file_matches_rule(test_file, &file_context));
+                assert!(!title.file_matches_rule(test_file, &file_context));
+            })
+        });
+    }
+
+    #[test]
+    fn tag_rule_requires_colon() {
+        assert!(TagRule::parse("author", Some(Operator::Equal), "nodder").is_err());
+    }
+}
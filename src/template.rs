@@ -0,0 +1,741 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Utc};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+
+use content_type::ContentType;
+use context::{stays_inside, MapFileContext, TimeZone};
+use error::*;
+use metadata::TimeKind;
+use photo::PhotoMetadata;
+use tags::{read_tag, tag_names};
+
+/// How times are written when a time variable such as `{mtime}` has no format of its own.
+const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d";
+
+/// Values for template variables that are known when a rule matches a file, e.g. regex capture groups.
+pub type TemplateVariables = HashMap<String, String>;
+
+/// Text that may contain `${variable}` or `${variable:format}` placeholders, which are filled in
+/// for each file that is mapped. The `$` is optional, but `{variable}` without it is left as it is written
+/// unless `variable` is known, so that directories containing braces keep working. `{{` is a literal `{`.
+#[derive(Clone, Debug)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Variable(String, Option<Format>),
+    /// A placeholder written without a `$`, with the text it was written as.
+    Bare(String, Option<Format>, String),
+}
+
+/// How a variable's value is written, given after a `:` in its placeholder.
+#[derive(Clone, Debug, PartialEq)]
+enum Format {
+    Transform(Transform),
+    /// Zero-padding of numbers to a width, written with a leading zero, e.g. `{track:02}`.
+    Pad(usize),
+    /// A strftime-style format for a time variable, e.g. `{mtime:%Y}`.
+    Time(TimeKind, String),
+}
+
+/// A case transform applied to a variable's value, written as `${variable:transform}`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Transform {
+    Upper,
+    Lower,
+    Title,
+    Slug,
+}
+
+impl Transform {
+    fn parse(transform: &str) -> Option<Transform> {
+        match transform {
+            "upper" => Some(Transform::Upper),
+            "lower" => Some(Transform::Lower),
+            "title" => Some(Transform::Title),
+            "slug" => Some(Transform::Slug),
+            _ => None,
+        }
+    }
+
+    fn apply(self, value: &str) -> String {
+        match self {
+            Transform::Upper => value.to_uppercase(),
+            Transform::Lower => value.to_lowercase(),
+            Transform::Title => title_case(value),
+            Transform::Slug => slug(value),
+        }
+    }
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template> {
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut remaining = template;
+        while let Some(start) = remaining.find('{') {
+            let after_start = &remaining[start + 1..];
+            if let Some(after_brace) = after_start.strip_prefix('{') {
+                literal.push_str(&remaining[..=start]);
+                remaining = after_brace;
+                continue;
+            }
+            let (before, explicit) = match remaining[..start].strip_suffix('$') {
+                Some(before) => (before, true),
+                None => (&remaining[..start], false),
+            };
+            let end = match after_start.find('}') {
+                Some(end) => end,
+                None if explicit => bail!("Unterminated variable in template {}", template),
+                None => break,
+            };
+            let placeholder = &after_start[..end];
+            let segment = match parse_placeholder(placeholder) {
+                Ok((name, format)) if explicit => Segment::Variable(name, format),
+                Ok((name, format)) => Segment::Bare(name, format, format!("{{{}}}", placeholder)),
+                Err(error) if explicit || is_builtin_variable(placeholder_name(placeholder)) => {
+                    return Err(error).chain_err(|| format!("Invalid template {}", template));
+                }
+                // Braces that aren't a placeholder are written as they are
+                Err(_) => {
+                    literal.push_str(&remaining[..=start]);
+                    remaining = after_start;
+                    continue;
+                }
+            };
+            literal.push_str(before);
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(literal.split_off(0)));
+            }
+            segments.push(segment);
+            remaining = &after_start[end + 1..];
+        }
+        literal.push_str(remaining);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        Ok(Template {
+            source: template.to_string(),
+            segments,
+        })
+    }
+
+    pub fn render(&self, file: &Path, variables: &TemplateVariables, file_context: &MapFileContext) -> Result<PathBuf> {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            let (name, format, written) = match segment {
+                Segment::Literal(text) => {
+                    rendered.push_str(text);
+                    continue;
+                }
+                Segment::Variable(name, format) => (name, format, None),
+                Segment::Bare(name, format, written) => (name, format, Some(written)),
+            };
+            match format {
+                Some(Format::Time(kind, format)) => {
+                    rendered.push_str(&format_time(*kind, file, format, file_context.time_zone)?)
+                }
+                _ => match lookup_variable(name, file, variables, file_context)? {
+                    Some(value) => match format {
+                        Some(Format::Transform(transform)) => rendered.push_str(&transform.apply(&value)),
+                        Some(Format::Pad(width)) => rendered.push_str(&pad(&value, *width)),
+                        _ => rendered.push_str(&value),
+                    },
+                    None => match written {
+                        // Not a variable, so the braces are part of the path
+                        Some(written) => rendered.push_str(written),
+                        None => bail!(
+                            "Unknown variable {{{}}} in template {} for file {}",
+                            name,
+                            self.source,
+                            file.to_string_lossy()
+                        ),
+                    },
+                },
+            }
+        }
+
+        let rendered = PathBuf::from(rendered);
+        if !stays_inside(&rendered) {
+            bail!("Template {} rendered '{}' for file {}, which is outside of the destination directory", self.source,
+                  rendered.to_string_lossy(), file.to_string_lossy());
+        }
+
+        Ok(rendered)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Destination of a mapped file: a directory relative to the destination directory and, optionally,
+/// a new name for the file. Written as `<directory template> [-> <file name template>]`, where the `->` has
+/// whitespace on both sides so that directories can contain `->`.
+#[derive(Clone, Debug)]
+pub struct Destination {
+    directory: Template,
+    file_name: Option<Template>,
+}
+
+impl Destination {
+    pub fn parse(destination: &str) -> Result<Destination> {
+        match file_name_separator(destination) {
+            Some(separator) => {
+                let file_name = destination[separator + 2..].trim();
+                if file_name.is_empty() {
+                    bail!("Empty file name template in destination {}", destination);
+                }
+                Ok(Destination {
+                    directory: Template::parse(destination[..separator].trim())?,
+                    file_name: Some(Template::parse(file_name)?),
+                })
+            }
+            None => Ok(Destination {
+                directory: Template::parse(destination)?,
+                file_name: None,
+            }),
+        }
+    }
+
+    pub fn render_directory(&self, file: &Path, variables: &TemplateVariables, file_context: &MapFileContext)
+        -> Result<PathBuf> {
+        self.directory.render(file, variables, file_context)
+    }
+
+    /// Renders the new name for `file`, or `None` if the file keeps its original name.
+    pub fn render_file_name(&self, file: &Path, variables: &TemplateVariables, file_context: &MapFileContext)
+        -> Result<Option<OsString>> {
+        let template = match self.file_name {
+            Some(ref template) => template,
+            None => return Ok(None),
+        };
+        let file_name = template.render(file, variables, file_context)?;
+        let mut components = file_name.components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) => Ok(Some(name.to_os_string())),
+            _ => bail!(
+                "File name template {} rendered '{}' for file {}, which is not a valid file name",
+                template,
+                file_name.to_string_lossy(),
+                file.to_string_lossy()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.file_name {
+            Some(ref file_name) => write!(f, "{} -> {}", self.directory, file_name),
+            None => write!(f, "{}", self.directory),
+        }
+    }
+}
+
+/// Where the first `->` with whitespace (or the start or end of the destination) on both sides is.
+fn file_name_separator(destination: &str) -> Option<usize> {
+    destination.match_indices("->")
+        .map(|(index, _)| index)
+        .find(|&index| {
+            let before = destination[..index].chars().next_back();
+            let after = destination[index + 2..].chars().next();
+            before.is_none_or(char::is_whitespace) && after.is_none_or(char::is_whitespace)
+        })
+}
+
+/// Parses the name and format of a placeholder, the text between its braces.
+fn parse_placeholder(placeholder: &str) -> Result<(String, Option<Format>)> {
+    let name = placeholder_name(placeholder);
+    if name.is_empty() {
+        bail!("Empty variable name");
+    }
+    let format = match placeholder.split_once(':') {
+        Some((_, format)) => Some(parse_format(name, format)?),
+        None => None,
+    };
+    Ok((name.to_string(), format))
+}
+
+fn placeholder_name(placeholder: &str) -> &str {
+    placeholder.split(':').next().unwrap_or_default().trim()
+}
+
+/// Whether `name` is a variable of every file, rather than a capture group of a rule's regex.
+fn is_builtin_variable(name: &str) -> bool {
+    ["name", "stem", "ext", "type", "make", "model", "camera"].contains(&name)
+        || tag_names().contains(&name)
+        || TimeKind::from_name(name).is_some()
+}
+
+/// Parses the format after the `:` in `{name:format}`: a case transform, a zero-padded width, or a
+/// strftime-style format if `name` is a time variable.
+fn parse_format(name: &str, format: &str) -> Result<Format> {
+    let trimmed = format.trim();
+    if let Some(transform) = Transform::parse(trimmed) {
+        return Ok(Format::Transform(transform));
+    }
+    if trimmed.starts_with('0') {
+        if let Ok(width) = trimmed.parse::<usize>() {
+            return Ok(Format::Pad(width));
+        }
+    }
+    match TimeKind::from_name(name) {
+        Some(kind) => {
+            if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+                bail!("Invalid time format '{}' for variable {}", format, name);
+            }
+            Ok(Format::Time(kind, format.to_string()))
+        }
+        None => bail!("Unknown format '{}' for variable {}", format, name),
+    }
+}
+
+fn lookup_variable(name: &str, file: &Path, variables: &TemplateVariables, file_context: &MapFileContext)
+    -> Result<Option<String>> {
+    if let Some(value) = variables.get(name) {
+        return Ok(Some(value.clone()));
+    }
+    let value = match name {
+        "name" => file.file_name().map(|name| name.to_string_lossy().into_owned()),
+        "stem" => file.file_stem().map(|stem| stem.to_string_lossy().into_owned()),
+        "ext" => Some(
+            file.extension()
+                .map(|extension| extension.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        ),
+        "type" => Some(
+            ContentType::detect(file)?
+                .map(|content_type| content_type.name())
+                .unwrap_or("unknown")
+                .to_string(),
+        ),
+        "make" | "model" | "camera" => {
+            let photo = PhotoMetadata::read(file)?;
+            let value = match name {
+                "make" => photo.and_then(|photo| photo.make()),
+                "model" => photo.and_then(|photo| photo.model()),
+                _ => photo.and_then(|photo| photo.camera()),
+            };
+            Some(path_component(name, value.unwrap_or_else(|| "unknown".to_string()))?)
+        }
+        _ if tag_names().contains(&name) => {
+            let value = read_tag(name, file)?;
+            let fallback = match name {
+                "title" => file.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default(),
+                "track" | "disc" => "0".to_string(),
+                _ => "unknown".to_string(),
+            };
+            Some(path_component(name, value.unwrap_or(fallback))?)
+        }
+        _ => match TimeKind::from_name(name) {
+            Some(kind) => Some(format_time(kind, file, DEFAULT_TIME_FORMAT, file_context.time_zone)?),
+            None => None,
+        },
+    };
+
+    Ok(value)
+}
+
+/// Makes a value read from a file's metadata safe to use as a single component of a path, as anyone who made
+/// the file could have put path separators or `..` in it.
+fn path_component(name: &str, value: String) -> Result<String> {
+    let value = value.replace(['/', '\\'], "_");
+    if value == ".." {
+        bail!("Variable {{{}}} is '..', which would map files out of their destination", name);
+    }
+    Ok(value)
+}
+
+fn format_time(kind: TimeKind, file: &Path, format: &str, time_zone: TimeZone) -> Result<String> {
+    let time = kind.of(file)?;
+    let formatted = match time_zone {
+        TimeZone::Local => DateTime::<Local>::from(time).format(format).to_string(),
+        TimeZone::Utc => DateTime::<Utc>::from(time).format(format).to_string(),
+    };
+
+    Ok(formatted)
+}
+
+/// Pads numbers with leading zeros to `width` digits. Other values are left as they are.
+fn pad(value: &str, width: usize) -> String {
+    if !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_digit()) {
+        format!("{:0>width$}", value, width = width)
+    } else {
+        value.to_string()
+    }
+}
+
+fn title_case(value: &str) -> String {
+    let mut titled = String::with_capacity(value.len());
+    let mut at_word_start = true;
+    for character in value.chars() {
+        if at_word_start {
+            titled.extend(character.to_uppercase());
+        } else {
+            titled.extend(character.to_lowercase());
+        }
+        at_word_start = character.is_whitespace() || character == '-' || character == '_';
+    }
+
+    titled
+}
+
+fn slug(value: &str) -> String {
+    let mut slug = String::with_capacity(value.len());
+    for character in value.chars().flat_map(char::to_lowercase) {
+        if character.is_alphanumeric() {
+            slug.push(character);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+
+    slug
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use testutils::*;
+
+    #[test]
+    fn template_without_variables_renders_literally() {
+        let template = Template::parse("Books/Design").unwrap();
+        let rendered = template.render(Path::new("book.pdf"), &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+        assert_eq!(rendered, PathBuf::from("Books/Design"));
+    }
+
+    #[test]
+    fn template_renders_file_variables() {
+        let template = Template::parse("${ext}/${stem}/${name}").unwrap();
+        let rendered = template.render(Path::new("./in/book.pdf"), &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+        assert_eq!(rendered, PathBuf::from("pdf/book/book.pdf"));
+    }
+
+    #[test]
+    fn template_renders_captured_variables() {
+        let mut variables = TemplateVariables::new();
+        variables.insert("1".to_string(), "report".to_string());
+        variables.insert("year".to_string(), "2019".to_string());
+        let template = Template::parse("${1}/${year}").unwrap();
+        let rendered = template.render(Path::new("report_2019.pdf"), &variables, &dummy_map_file_context()).unwrap();
+        assert_eq!(rendered, PathBuf::from("report/2019"));
+    }
+
+    #[test]
+    fn template_captured_variables_take_precedence() {
+        let mut variables = TemplateVariables::new();
+        variables.insert("name".to_string(), "captured".to_string());
+        let template = Template::parse("${name}").unwrap();
+        let rendered = template.render(Path::new("file.txt"), &variables, &dummy_map_file_context()).unwrap();
+        assert_eq!(rendered, PathBuf::from("captured"));
+    }
+
+    #[test]
+    fn template_unknown_variable() {
+        let template = Template::parse("${unknown}").unwrap();
+        assert!(template.render(Path::new("file.txt"), &TemplateVariables::new(), &dummy_map_file_context()).is_err());
+    }
+
+    #[test]
+    fn template_renders_content_type() {
+        with_default_test_directory(|test_directory| {
+            let book = test_directory.join("book.bin");
+            File::create(&book).unwrap().write_all(b"%PDF-1.4\n").unwrap();
+            let unknown = test_directory.join("notes.pdf");
+            File::create(&unknown).unwrap().write_all(b"notes").unwrap();
+            let template = Template::parse("${type}").unwrap();
+            assert_eq!(template.render(&book, &TemplateVariables::new(), &dummy_map_file_context()).unwrap(), PathBuf::from("pdf"));
+            assert_eq!(template.render(&unknown, &TemplateVariables::new(), &dummy_map_file_context()).unwrap(), PathBuf::from("unknown"));
+        });
+    }
+
+    #[test]
+    fn template_dollar_sign_is_optional() {
+        let template = Template::parse("{ext}/${stem}/{{literal}").unwrap();
+        let rendered = template.render(Path::new("book.pdf"), &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+        assert_eq!(rendered, PathBuf::from("pdf/book/{literal}"));
+    }
+
+    #[test]
+    fn template_keeps_braces_that_are_not_variables() {
+        let mut variables = TemplateVariables::new();
+        variables.insert("1".to_string(), "captured".to_string());
+        let template = Template::parse("{unknown}/{1}/{2}/{}/{unknown:sideways}/{unterminated").unwrap();
+        let rendered = template.render(Path::new("book.pdf"), &variables, &dummy_map_file_context()).unwrap();
+        assert_eq!(rendered, PathBuf::from("{unknown}/captured/{2}/{}/{unknown:sideways}/{unterminated"));
+        assert!(Template::parse("{stem:sideways}").is_err());
+    }
+
+    #[test]
+    fn template_keeps_dollar_signs_before_placeholders() {
+        let mut variables = TemplateVariables::new();
+        variables.insert("x".to_string(), "X".to_string());
+        let template = Template::parse("a$${x}/$$/b$").unwrap();
+        let rendered = template.render(Path::new("file.txt"), &variables, &dummy_map_file_context()).unwrap();
+        assert_eq!(rendered, PathBuf::from("a$X/$$/b$"));
+    }
+
+    #[test]
+    fn template_renders_formatted_times() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let file_context = MapFileContext {
+                    time_zone: TimeZone::Utc,
+                    ..dummy_map_file_context()
+                };
+                let modified = DateTime::<Utc>::from(fs::metadata(test_file).unwrap().modified().unwrap());
+                let template = Template::parse("Photos/{mtime:%Y}/{mtime:%m %B}/{mtime}").unwrap();
+                let rendered = template.render(test_file, &TemplateVariables::new(), &file_context).unwrap();
+                let expected = modified.format("Photos/%Y/%m %B/%Y-%m-%d").to_string();
+                assert_eq!(rendered, PathBuf::from(expected));
+            })
+        });
+    }
+
+    #[test]
+    fn template_renders_photo_variables() {
+        with_default_test_directory(|test_directory| {
+            let photo = test_directory.join("IMG_1234.TIF");
+            File::create(&photo).unwrap().write_all(&tiff_with_exif()).unwrap();
+            let template = Template::parse("Photos/{taken:%Y}/{camera}").unwrap();
+            let rendered = template.render(&photo, &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+            assert_eq!(rendered, PathBuf::from("Photos/2023/Canon EOS R6"));
+        });
+    }
+
+    #[test]
+    fn template_photo_variables_cannot_add_directories() {
+        with_default_test_directory(|test_directory| {
+            let photo = test_directory.join("IMG_1234.TIF");
+            let tiff = tiff_with_exif();
+            let make = tiff.windows(6).position(|window| window == b"Canon\0").unwrap();
+            let mut tiff_with_separators = tiff.clone();
+            tiff_with_separators[make..make + 5].copy_from_slice(b"C/a\\n");
+            File::create(&photo).unwrap().write_all(&tiff_with_separators).unwrap();
+            let template = Template::parse("Photos/{make}/{camera}").unwrap();
+            let rendered = template.render(&photo, &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+            assert_eq!(rendered, PathBuf::from("Photos/C_a_n/C_a_n Canon EOS R6"));
+
+            let mut tiff_with_parent = tiff;
+            tiff_with_parent[make..make + 5].copy_from_slice(b"..\0\0\0");
+            File::create(&photo).unwrap().write_all(&tiff_with_parent).unwrap();
+            assert!(template.render(&photo, &TemplateVariables::new(), &dummy_map_file_context()).is_err());
+        });
+    }
+
+    #[test]
+    fn template_photo_variables_without_exif() {
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let modified = DateTime::<Local>::from(fs::metadata(test_file).unwrap().modified().unwrap());
+                let template = Template::parse("{taken:%Y}/{model}").unwrap();
+                let rendered = template.render(test_file, &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+                assert_eq!(rendered, PathBuf::from(modified.format("%Y/unknown").to_string()));
+            })
+        });
+    }
+
+    #[test]
+    fn template_pads_numbers() {
+        let mut variables = TemplateVariables::new();
+        variables.insert("1".to_string(), "7".to_string());
+        variables.insert("2".to_string(), "B".to_string());
+        let template = Template::parse("{1:02}-{1:003}-{2:02}").unwrap();
+        let rendered = template.render(Path::new("file.txt"), &variables, &dummy_map_file_context()).unwrap();
+        assert_eq!(rendered, PathBuf::from("07-007-B"));
+    }
+
+    #[test]
+    fn template_renders_audio_variables() {
+        with_default_test_directory(|test_directory| {
+            let song = test_directory.join("03 digital love.mp3");
+            File::create(&song).unwrap().write_all(&mp3_with_id3()).unwrap();
+            let destination = Destination::parse("Music/{artist}/{album} -> {track:02} - {title}.{ext}").unwrap();
+            let file_context = dummy_map_file_context();
+            let variables = TemplateVariables::new();
+            assert_eq!(
+                destination.render_directory(&song, &variables, &file_context).unwrap(),
+                PathBuf::from("Music/Daft Punk/Discovery")
+            );
+            assert_eq!(
+                destination.render_file_name(&song, &variables, &file_context).unwrap(),
+                Some(OsString::from("03 - Digital Love.mp3"))
+            );
+        });
+    }
+
+    #[test]
+    fn template_audio_variables_cannot_add_directories() {
+        with_default_test_directory(|test_directory| {
+            let song = test_directory.join("song.mp3");
+            let mp3 = mp3_with_id3();
+            let artist = mp3.windows(9).position(|window| window == b"Daft Punk").unwrap();
+            let album = mp3.windows(9).position(|window| window == b"Discovery").unwrap();
+            let mut mp3_with_separators = mp3.clone();
+            mp3_with_separators[artist..artist + 9].copy_from_slice(b"../../esc");
+            mp3_with_separators[album..album + 9].copy_from_slice(b"AC/DC Liv");
+            File::create(&song).unwrap().write_all(&mp3_with_separators).unwrap();
+            let template = Template::parse("Music/{artist}/{album}").unwrap();
+            let rendered = template.render(&song, &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+            assert_eq!(rendered, PathBuf::from("Music/.._.._esc/AC_DC Liv"));
+
+            let mut mp3_with_parent = mp3;
+            mp3_with_parent[artist..artist + 9].copy_from_slice(b"..\0\0\0\0\0\0\0");
+            File::create(&song).unwrap().write_all(&mp3_with_parent).unwrap();
+            assert!(template.render(&song, &TemplateVariables::new(), &dummy_map_file_context()).is_err());
+        });
+    }
+
+    #[test]
+    fn template_outside_destination() {
+        let mut variables = TemplateVariables::new();
+        variables.insert("1".to_string(), "..".to_string());
+        let file = Path::new("file.txt");
+        assert!(Template::parse("../Books").unwrap().render(file, &variables, &dummy_map_file_context()).is_err());
+        assert!(Template::parse("/Books").unwrap().render(file, &variables, &dummy_map_file_context()).is_err());
+        assert!(Template::parse("Books/${1}/x").unwrap().render(file, &variables, &dummy_map_file_context()).is_err());
+        assert!(Destination::parse("Books -> ${1}").unwrap().render_file_name(file, &variables, &dummy_map_file_context()).is_err());
+        assert_eq!(Template::parse("./Books").unwrap().render(file, &variables, &dummy_map_file_context()).unwrap(),
+                   PathBuf::from("./Books"));
+    }
+
+    #[test]
+    fn template_audio_variables_without_tags() {
+        let template = Template::parse("{artist}/{track:02} - {title}").unwrap();
+        with_default_test_directory(|test_directory| {
+            with_default_test_file(test_directory, |test_file| {
+                let rendered = template.render(test_file, &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+                let expected = format!("unknown/00 - {}", test_file.file_stem().unwrap().to_string_lossy());
+                assert_eq!(rendered, PathBuf::from(expected));
+            })
+        });
+    }
+
+    #[test]
+    fn template_renders_ebook_variables() {
+        with_default_test_directory(|test_directory| {
+            let book = test_directory.join("evilbydesign.epub");
+            File::create(&book).unwrap().write_all(&epub_with_metadata()).unwrap();
+            let template = Template::parse("Books/{author}/{title}/{series}").unwrap();
+            let rendered = template.render(&book, &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+            assert_eq!(rendered, PathBuf::from("Books/Chris Nodder/Evil by Design/Design Classics"));
+        });
+    }
+
+    #[test]
+    fn template_ebook_variables_cannot_add_directories() {
+        with_default_test_directory(|test_directory| {
+            let book = test_directory.join("evilbydesign.pdf");
+            let pdf = String::from_utf8(pdf_with_info()).unwrap();
+            File::create(&book).unwrap().write_all(pdf.replace("Chris Nodder", "A/B\\\\C").as_bytes()).unwrap();
+            let template = Template::parse("Books/{author}/{title}").unwrap();
+            let rendered = template.render(&book, &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+            assert_eq!(rendered, PathBuf::from("Books/A_B_C/Evil by Design (2nd Edition)"));
+
+            File::create(&book).unwrap().write_all(pdf.replace("Chris Nodder", "..").as_bytes()).unwrap();
+            assert!(template.render(&book, &TemplateVariables::new(), &dummy_map_file_context()).is_err());
+        });
+    }
+
+    #[test]
+    fn template_invalid_time_format() {
+        assert!(Template::parse("{mtime:%Q}").is_err());
+        assert!(Template::parse("{stem:%Y}").is_err());
+    }
+
+    #[test]
+    fn template_time_of_missing_file() {
+        let template = Template::parse("{atime:%Y}").unwrap();
+        assert!(template.render(Path::new("does-not-exist"), &TemplateVariables::new(), &dummy_map_file_context()).is_err());
+    }
+
+    #[test]
+    fn template_unterminated_variable() {
+        assert!(Template::parse("Books/${1").is_err());
+    }
+
+    #[test]
+    fn template_empty_variable() {
+        assert!(Template::parse("Books/${}").is_err());
+    }
+
+    #[test]
+    fn template_applies_transforms() {
+        let template = Template::parse("${stem:upper}/${stem:lower}/${stem:title}/${stem:slug}").unwrap();
+        let rendered = template.render(Path::new("the essential_GUIDE.pdf"), &TemplateVariables::new(), &dummy_map_file_context()).unwrap();
+        assert_eq!(
+            rendered,
+            PathBuf::from("THE ESSENTIAL_GUIDE/the essential_guide/The Essential_Guide/the-essential-guide")
+        );
+    }
+
+    #[test]
+    fn template_unknown_transform() {
+        assert!(Template::parse("${stem:sideways}").is_err());
+    }
+
+    #[test]
+    fn destination_without_file_name() {
+        let destination = Destination::parse("Books/${ext}").unwrap();
+        let file = Path::new("book.pdf");
+        let variables = TemplateVariables::new();
+        assert_eq!(destination.render_directory(file, &variables, &dummy_map_file_context()).unwrap(), PathBuf::from("Books/pdf"));
+        assert_eq!(destination.render_file_name(file, &variables, &dummy_map_file_context()).unwrap(), None);
+    }
+
+    #[test]
+    fn destination_with_file_name() {
+        let destination = Destination::parse("Books -> Evil by Design.${ext}").unwrap();
+        let file = Path::new("evilbydesign.pdf");
+        let variables = TemplateVariables::new();
+        assert_eq!(destination.render_directory(file, &variables, &dummy_map_file_context()).unwrap(), PathBuf::from("Books"));
+        assert_eq!(
+            destination.render_file_name(file, &variables, &dummy_map_file_context()).unwrap(),
+            Some(OsString::from("Evil by Design.pdf"))
+        );
+    }
+
+    #[test]
+    fn destination_file_name_with_separator() {
+        let destination = Destination::parse("Books -> ${1}").unwrap();
+        let mut variables = TemplateVariables::new();
+        variables.insert("1".to_string(), "nested/name".to_string());
+        assert!(destination.render_file_name(Path::new("book.pdf"), &variables, &dummy_map_file_context()).is_err());
+    }
+
+    #[test]
+    fn destination_directory_with_arrow() {
+        let destination = Destination::parse("a->b/c -> d").unwrap();
+        let file = Path::new("book.pdf");
+        let variables = TemplateVariables::new();
+        assert_eq!(destination.render_directory(file, &variables, &dummy_map_file_context()).unwrap(), PathBuf::from("a->b/c"));
+        assert_eq!(destination.render_file_name(file, &variables, &dummy_map_file_context()).unwrap(), Some(OsString::from("d")));
+
+        let destination = Destination::parse("Books/x->y").unwrap();
+        assert_eq!(destination.render_directory(file, &variables, &dummy_map_file_context()).unwrap(), PathBuf::from("Books/x->y"));
+        assert_eq!(destination.render_file_name(file, &variables, &dummy_map_file_context()).unwrap(), None);
+    }
+
+    #[test]
+    fn destination_empty_file_name() {
+        assert!(Destination::parse("Books ->  ").is_err());
+    }
+}
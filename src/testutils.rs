@@ -34,7 +34,8 @@ pub fn dummy_map_file_context() -> MapFileContext {
     MapFileContext {
         source_dir: PathBuf::from("dummy-source-dir"),
         dest_dir: PathBuf::from("dummy-dest-dir"),
-        dry_run: false
+        dry_run: false,
+        ..Default::default()
     }
 }
 
@@ -57,10 +58,22 @@ where
     fs::create_dir_all(test_dir).unwrap();
     assert_eq!(test_dir.is_dir(), true);
 
+    // Clean up, even if the test fails
+    let _cleanup = RemoveOnDrop(test_dir.clone());
     test_method(test_dir);
+}
+
+/// Removes a test directory when dropped, including while a failed test unwinds.
+struct RemoveOnDrop(PathBuf);
 
-    // Clean up
-    fs::remove_dir_all(test_dir).unwrap();
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        if self.0.is_dir() {
+            if let Err(error) = fs::remove_dir_all(&self.0) {
+                println!("Unable to remove test directory {:?}: {}", self.0, error);
+            }
+        }
+    }
 }
 
 pub fn with_default_output_directory<F>(test_dir: &PathBuf, test_method: F)
@@ -93,4 +106,95 @@ pub fn random_string() -> String {
     let random_number = rand::random::<u32>();
     println!("Using random number: {:?}", random_number);
     random_number.to_string()
-}
\ No newline at end of file
+}
+
+/// A little-endian TIFF image without any pixels, but with EXIF data: a camera, a capture date and a GPS
+/// latitude.
+pub fn tiff_with_exif() -> Vec<u8> {
+    let mut tiff = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+    // IFD0 at 8: Make, Model, Exif IFD pointer and GPS IFD pointer, then the Make and Model text
+    tiff.extend_from_slice(&[4, 0]);
+    tiff.extend_from_slice(&ifd_entry(0x010F, 2, 6, 62));
+    tiff.extend_from_slice(&ifd_entry(0x0110, 2, 13, 68));
+    tiff.extend_from_slice(&ifd_entry(0x8769, 4, 1, 82));
+    tiff.extend_from_slice(&ifd_entry(0x8825, 4, 1, 120));
+    tiff.extend_from_slice(&[0, 0, 0, 0]);
+    tiff.extend_from_slice(b"Canon\0Canon EOS R6\0\0");
+    // Exif IFD at 82: DateTimeOriginal
+    tiff.extend_from_slice(&[1, 0]);
+    tiff.extend_from_slice(&ifd_entry(0x9003, 2, 20, 100));
+    tiff.extend_from_slice(&[0, 0, 0, 0]);
+    tiff.extend_from_slice(b"2023:05:06 07:08:09\0");
+    // GPS IFD at 120: GPSLatitudeRef, which is short enough to be stored within its entry
+    tiff.extend_from_slice(&[1, 0]);
+    tiff.extend_from_slice(&ifd_entry(0x0001, 2, 2, u32::from_le_bytes(*b"N\0\0\0")));
+    tiff.extend_from_slice(&[0, 0, 0, 0]);
+    tiff
+}
+
+fn ifd_entry(tag: u16, value_type: u16, count: u32, value: u32) -> Vec<u8> {
+    let mut entry = vec![];
+    entry.extend_from_slice(&tag.to_le_bytes());
+    entry.extend_from_slice(&value_type.to_le_bytes());
+    entry.extend_from_slice(&count.to_le_bytes());
+    entry.extend_from_slice(&value.to_le_bytes());
+    entry
+}
+
+/// The start of an MP3 file with an ID3v2.3 tag giving its artist, album, title and track number.
+pub fn mp3_with_id3() -> Vec<u8> {
+    let frames: &[(&[u8], &[u8])] = &[
+        (b"TPE1", b"Daft Punk"),
+        (b"TALB", b"Discovery"),
+        (b"TIT2", b"Digital Love"),
+        (b"TRCK", b"3/14"),
+    ];
+    let mut body = vec![];
+    for (id, text) in frames {
+        body.extend_from_slice(id);
+        body.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+        body.extend_from_slice(&[0, 0, 0]);
+        body.extend_from_slice(text);
+    }
+    let mut mp3 = b"ID3\x03\x00\x00\x00\x00".to_vec();
+    mp3.extend_from_slice(&[(body.len() >> 7) as u8 & 0x7F, body.len() as u8 & 0x7F]);
+    mp3.extend_from_slice(&body);
+    mp3.extend_from_slice(b"\xFF\xFB\x90\x64");
+    mp3
+}
+
+/// An EPUB with only a container and a package document, which gives a title, author, publisher and series.
+pub fn epub_with_metadata() -> Vec<u8> {
+    use std::io::{Cursor, Write};
+    use zip::write::{FileOptions, ZipWriter};
+    use zip::CompressionMethod;
+
+    let mut epub = ZipWriter::new(Cursor::new(vec![]));
+    epub.start_file("mimetype", FileOptions::default().compression_method(CompressionMethod::Stored)).unwrap();
+    epub.write_all(b"application/epub+zip").unwrap();
+    epub.start_file("META-INF/container.xml", FileOptions::default()).unwrap();
+    epub.write_all(br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#).unwrap();
+    epub.start_file("OEBPS/content.opf", FileOptions::default()).unwrap();
+    epub.write_all(br#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+    <dc:title>Evil by
+      Design</dc:title>
+    <dc:creator opf:role="aut">Chris Nodder</dc:creator>
+    <dc:publisher>Wiley &amp; Sons</dc:publisher>
+    <meta content="Design Classics" name="calibre:series"/>
+  </metadata>
+</package>"#).unwrap();
+    epub.finish().unwrap().into_inner()
+}
+
+/// A PDF without pages, but with an Info dictionary giving its title and author.
+pub fn pdf_with_info() -> Vec<u8> {
+    b"%PDF-1.4\n1 0 obj\n<< /Type /Catalog >>\nendobj\n\
+      7 0 obj\n<</Title(Evil by Design \\(2nd Edition\\))/Author (Chris Nodder)>>\nendobj\n\
+      trailer\n<< /Root 1 0 R /Info 7 0 R >>\n%%EOF\n"
+        .to_vec()
+}